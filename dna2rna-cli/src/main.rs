@@ -1,16 +1,22 @@
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use dna2rna::RnaStore;
 use log;
+use std::fmt;
 use std::fs;
+use std::io;
 use std::io::Write;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 fn main() {
     env_logger::init();
 
     let matches = App::new("dna2rna-cli")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("INPUT")
                 .help("Sets the input file to use")
-                .required(true)
+                .required_unless("RESUME")
                 .index(1),
         )
         .arg(
@@ -25,29 +31,433 @@ fn main() {
                 .short("o")
                 .long("output")
                 .takes_value(true)
-                .help("Sets the output file name"),
+                .help("Sets the output file name, - for stdout"),
+        )
+        .arg(
+            Arg::with_name("TRACE")
+                .short("t")
+                .long("trace")
+                .takes_value(true)
+                .help("Writes a trace of every step to this file"),
+        )
+        .arg(
+            Arg::with_name("TRACE_FORMAT")
+                .long("trace-format")
+                .takes_value(true)
+                .possible_values(&["json", "binary"])
+                .default_value("json")
+                .help("Sets the trace format, JSON Lines or binary"),
+        )
+        .arg(
+            Arg::with_name("RESUME")
+                .short("r")
+                .long("resume")
+                .takes_value(true)
+                .conflicts_with_all(&["INPUT", "PREFIX"])
+                .help("Resumes from a checkpoint instead of an input file"),
+        )
+        .arg(
+            Arg::with_name("CHECKPOINT")
+                .short("c")
+                .long("checkpoint")
+                .takes_value(true)
+                .help("Writes a checkpoint to this file when execution stops"),
+        )
+        .arg(
+            Arg::with_name("STEPS")
+                .short("s")
+                .long("steps")
+                .takes_value(true)
+                .help("Stops after this many steps"),
+        )
+        .arg(
+            Arg::with_name("MAX_DNA")
+                .long("max-dna")
+                .takes_value(true)
+                .help("Stops when the DNA grows past this many bases"),
+        )
+        .arg(
+            Arg::with_name("TIMEOUT")
+                .long("timeout")
+                .takes_value(true)
+                .help("Stops after this many seconds"),
+        )
+        .arg(
+            Arg::with_name("PROFILE")
+                .long("profile")
+                .takes_value(true)
+                .help("Prints this many of the pattern sites that took the most time"),
+        )
+        .subcommand(
+            SubCommand::with_name("asm")
+                .about("Assembles DNA from patterns and templates")
+                .arg(
+                    Arg::with_name("INPUT")
+                        .help("Sets the source file to assemble")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("OUTPUT")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .help("Sets the output file name, default is stdout"),
+                ),
+        )
+        .subcommand(
+            draw_args(SubCommand::with_name("render"))
+                .about("Runs DNA and draws the RNA it makes to an image")
+                .arg(
+                    Arg::with_name("OUTPUT")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Sets the image file name, .ppm for PPM, otherwise PNG"),
+                )
+                .arg(
+                    Arg::with_name("ALPHA")
+                        .long("alpha")
+                        .help("Keeps the alpha channel in PNG output"),
+                ),
+        )
+        .subcommand(
+            draw_args(SubCommand::with_name("score"))
+                .about("Runs DNA and counts the pixels that differ from a target image")
+                .arg(
+                    Arg::with_name("TARGET")
+                        .short("T")
+                        .long("target")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Sets the target image, .ppm for PPM, otherwise PNG"),
+                )
+                .arg(
+                    Arg::with_name("DIFF")
+                        .short("d")
+                        .long("diff")
+                        .takes_value(true)
+                        .help("Writes a heatmap of the differing pixels to this file"),
+                ),
         )
         .get_matches();
 
     log::debug!("matches: {:?}", matches);
 
-    let filename = matches.value_of("INPUT").unwrap();
+    if let Some(asm_matches) = matches.subcommand_matches("asm") {
+        assemble(
+            asm_matches.value_of("INPUT").unwrap(),
+            asm_matches.value_of("OUTPUT"),
+        );
+        return;
+    }
+
+    if let Some(render_matches) = matches.subcommand_matches("render") {
+        render(render_matches);
+        return;
+    }
+
+    if let Some(score_matches) = matches.subcommand_matches("score") {
+        score(score_matches);
+        return;
+    }
+
     let prefix = matches.value_of("PREFIX");
     let output = matches.value_of("OUTPUT");
+    let trace = matches.value_of("TRACE");
+    let steps = match parse_arg::<usize>(&matches, "STEPS") {
+        Ok(n) => n,
+        Err(()) => return,
+    };
+    let max_dna_len = match parse_arg::<usize>(&matches, "MAX_DNA") {
+        Ok(n) => n,
+        Err(()) => return,
+    };
+    let timeout = match parse_arg::<f64>(&matches, "TIMEOUT") {
+        Ok(t) => t,
+        Err(()) => return,
+    };
 
-    if let Ok(dna) = fs::read_to_string(filename) {
-        log::info!("prefix: {:?}", prefix);
-        let mut s = dna2rna::VecRnaStore::new();
-        let mut d = dna2rna::Dna2Rna::new(&mut s);
-        d.set_dna_and_prefix(&dna, prefix);
-        d.execute();
-        if let Some(out_filename) = output {
-            let mut buf = fs::File::create(out_filename).unwrap();
-            for r in &s.rna {
-                buf.write(r.as_bytes()).expect("OH NOES");
+    let profile = match parse_arg::<usize>(&matches, "PROFILE") {
+        Ok(n) => n,
+        Err(()) => return,
+    };
+
+    let checkpoint_filename = matches.value_of("CHECKPOINT");
+    // keep the rna only if a checkpoint needs it, stream it to the output
+    let kept = checkpoint_filename.map(|_| dna2rna::VecRnaStore::new());
+    let out: Option<Box<dyn Write>> = match output {
+        Some("-") => Some(Box::new(io::stdout())),
+        Some(out_filename) => match fs::File::create(out_filename) {
+            Ok(f) => Some(Box::new(f)),
+            Err(e) => {
+                log::error!("error creating {}: {}", out_filename, e);
+                return;
+            }
+        },
+        None => None,
+    };
+    let mut s = dna2rna::TeeRnaStore::new(kept, out.map(dna2rna::WriterRnaStore::new));
+    let mut start = None;
+    let mut dna = String::new();
+    if let Some(filename) = matches.value_of("RESUME") {
+        match fs::File::open(filename).and_then(dna2rna::Checkpoint::read_from) {
+            Ok(checkpoint) => {
+                for r in checkpoint.rna {
+                    s.store(r);
+                }
+                start = Some(checkpoint.snapshot);
+            }
+            Err(e) => {
+                log::error!("error reading checkpoint {}: {}", filename, e);
+                return;
             }
         }
     } else {
-        log::error!("error reading file {}", filename);
+        let filename = matches.value_of("INPUT").unwrap();
+        match fs::read_to_string(filename) {
+            Ok(d) => dna = d,
+            Err(_) => {
+                log::error!("error reading file {}", filename);
+                return;
+            }
+        }
+    }
+
+    log::info!("prefix: {:?}", prefix);
+    let mut profiler = dna2rna::profile::Profiler::new();
+    let mut json_tracer = None;
+    let mut binary_tracer = None;
+    if let Some(trace_filename) = trace {
+        let file = match fs::File::create(trace_filename) {
+            Ok(f) => io::BufWriter::new(f),
+            Err(e) => {
+                log::error!("error creating {}: {}", trace_filename, e);
+                return;
+            }
+        };
+        match matches.value_of("TRACE_FORMAT") {
+            Some("binary") => binary_tracer = Some(dna2rna::BinaryTracer::new(file)),
+            _ => json_tracer = Some(dna2rna::JsonLinesTracer::new(file)),
+        }
+    }
+    let mut d = dna2rna::Dna2Rna::new(&mut s);
+    if profile.is_some() {
+        d.add_observer(&mut profiler);
+        d.set_track_origins(true);
+    }
+    if let Some(t) = json_tracer.as_mut() {
+        d.set_tracer(t);
+    }
+    if let Some(t) = binary_tracer.as_mut() {
+        d.set_tracer(t);
+    }
+    match &start {
+        Some(snapshot) => d.restore(snapshot),
+        None => {
+            if let Err(e) = d.set_dna_and_prefix(&dna, prefix) {
+                log::error!("error loading dna: {}", e);
+                return;
+            }
+        }
+    }
+    d.set_limits(dna2rna::Limits {
+        max_steps: steps,
+        max_dna_len,
+        deadline: timeout.map(|t| Instant::now() + Duration::from_secs_f64(t)),
+        cancel: None,
+    });
+    match d.execute() {
+        Ok(outcome) => log::info!("{:?}: {:?}", outcome, d.stats()),
+        Err(e) => log::error!("execution stopped: {}", e),
+    }
+    let snapshot = d.snapshot();
+    let traced = match (json_tracer, binary_tracer) {
+        (Some(t), _) => t.finish().map(|_| ()),
+        (_, Some(t)) => t.finish().map(|_| ()),
+        _ => Ok(()),
+    };
+    if let Err(e) = traced {
+        log::error!("error writing trace: {}", e);
+    }
+    if let Some(n) = profile {
+        println!("{}", dna2rna::profile::SITE_HEADER);
+        for site in profiler.hottest(n) {
+            println!("{}", site);
+        }
+    }
+    if let Some(Err(e)) = s.second.map(|w| w.finish()) {
+        log::error!("error writing rna: {}", e);
+    }
+    if let (Some(filename), Some(kept)) = (checkpoint_filename, s.first) {
+        let checkpoint = dna2rna::Checkpoint {
+            snapshot,
+            rna: kept.rna,
+        };
+        let written =
+            fs::File::create(filename).and_then(|f| checkpoint.write_to(io::BufWriter::new(f)));
+        if let Err(e) = written {
+            log::error!("error writing checkpoint {}: {}", filename, e);
+        }
+    }
+}
+
+/// Parses an optional argument, logging an error if it's malformed.
+fn parse_arg<T: FromStr>(matches: &ArgMatches, name: &str) -> Result<Option<T>, ()>
+where
+    T::Err: fmt::Display,
+{
+    match matches.value_of(name).map(|v| v.parse::<T>()) {
+        Some(Ok(v)) => Ok(Some(v)),
+        Some(Err(e)) => {
+            log::error!("bad value for {}: {}", name, e);
+            Err(())
+        }
+        None => Ok(None),
+    }
+}
+
+fn assemble(filename: &str, output: Option<&str>) {
+    let src = match fs::read_to_string(filename) {
+        Ok(src) => src,
+        Err(_) => {
+            log::error!("error reading file {}", filename);
+            return;
+        }
+    };
+    let dna = match dna2rna::asm::assemble(&src) {
+        Ok(dna) => dna,
+        Err(e) => {
+            log::error!("error assembling {}: {}", filename, e);
+            return;
+        }
+    };
+    match output {
+        Some(out_filename) => {
+            if let Err(e) = fs::write(out_filename, dna.to_string()) {
+                log::error!("error writing {}: {}", out_filename, e);
+            }
+        }
+        None => println!("{}", dna),
+    }
+}
+
+/// Adds the arguments of the subcommands that run DNA and draw its RNA.
+fn draw_args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.arg(
+        Arg::with_name("INPUT")
+            .help("Sets the input file to use")
+            .required(true)
+            .index(1),
+    )
+    .arg(
+        Arg::with_name("PREFIX")
+            .short("p")
+            .long("prefix")
+            .takes_value(true)
+            .help("Sets the prefix to use"),
+    )
+    .arg(
+        Arg::with_name("STEPS")
+            .short("s")
+            .long("steps")
+            .takes_value(true)
+            .help("Stops the DNA after this many steps"),
+    )
+}
+
+/// Runs the DNA given by `draw_args` and draws all the RNA it made.
+fn draw(matches: &ArgMatches) -> Option<rna2fuun::Bitmap> {
+    let filename = matches.value_of("INPUT").unwrap();
+    let steps = parse_arg::<usize>(matches, "STEPS").ok()?;
+    let dna = match fs::read_to_string(filename) {
+        Ok(d) => d,
+        Err(_) => {
+            log::error!("error reading file {}", filename);
+            return None;
+        }
+    };
+    let mut rna = dna2rna::VecRnaStore::new();
+    let mut d = dna2rna::Dna2Rna::new(&mut rna);
+    if let Err(e) = d.set_dna_and_prefix(&dna, matches.value_of("PREFIX")) {
+        log::error!("error loading dna: {}", e);
+        return None;
+    }
+    d.set_limits(dna2rna::Limits {
+        max_steps: steps,
+        ..dna2rna::Limits::default()
+    });
+    match d.execute() {
+        Ok(outcome) => log::info!("{:?}: {:?}", outcome, d.stats()),
+        Err(e) => log::error!("execution stopped: {}", e),
+    }
+    drop(d);
+    let mut fuun = rna2fuun::Fuun::new(rna2fuun::Canvas::default());
+    for r in rna.rna {
+        fuun.add_rna_command(r);
+    }
+    Some(fuun.build().clone())
+}
+
+fn write_image(bitmap: &rna2fuun::Bitmap, filename: &str, alpha: bool) {
+    let written = fs::File::create(filename).and_then(|f| {
+        let f = io::BufWriter::new(f);
+        if filename.ends_with(".ppm") {
+            bitmap.write_ppm(f)
+        } else {
+            bitmap.write_png(f, alpha)
+        }
+    });
+    if let Err(e) = written {
+        log::error!("error writing image {}: {}", filename, e);
+    }
+}
+
+fn render(matches: &ArgMatches) {
+    if let Some(bitmap) = draw(matches) {
+        write_image(
+            &bitmap,
+            matches.value_of("OUTPUT").unwrap(),
+            matches.is_present("ALPHA"),
+        );
+    }
+}
+
+fn score(matches: &ArgMatches) {
+    let filename = matches.value_of("TARGET").unwrap();
+    let target = fs::File::open(filename).and_then(|f| {
+        let f = io::BufReader::new(f);
+        if filename.ends_with(".ppm") {
+            rna2fuun::Bitmap::read_ppm(f)
+        } else {
+            rna2fuun::Bitmap::read_png(f)
+        }
+    });
+    let target = match target {
+        Ok(t) => t,
+        Err(e) => {
+            log::error!("error reading image {}: {}", filename, e);
+            return;
+        }
+    };
+    let bitmap = match draw(matches) {
+        Some(b) => b,
+        None => return,
+    };
+    if bitmap.canvas != target.canvas {
+        log::error!(
+            "{} is {}x{}, not {}x{}",
+            filename,
+            target.canvas.width,
+            target.canvas.height,
+            bitmap.canvas.width,
+            bitmap.canvas.height
+        );
+        return;
+    }
+    println!("{}", rna2fuun::score::risk(&bitmap, &target));
+    if let Some(diff) = matches.value_of("DIFF") {
+        write_image(&rna2fuun::score::heatmap(&bitmap, &target), diff, false);
     }
 }
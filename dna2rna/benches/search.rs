@@ -0,0 +1,77 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use dna2rna::{Base, Dna};
+
+/// The scan `match_replace` used before `Dna::find`: compare the needle at
+/// every position of the haystack.
+fn naive_find(hay: &Dna, needle: &[Base], from: usize) -> Option<usize> {
+    let mut n = from;
+    let mut hay_iter = hay.iter_at(n);
+    loop {
+        if n + needle.len() > hay.len() {
+            return None;
+        }
+        if hay_iter
+            .clone()
+            .take(needle.len())
+            .eq(needle.iter().cloned())
+        {
+            return Some(n);
+        }
+        hay_iter.next();
+        n += 1;
+    }
+}
+
+/// Deterministic pseudo-random DNA roughly the size of endo.dna.
+fn endo_sized(len: usize) -> Dna {
+    let mut x = 0x2545_f491_4f6c_dd1du64;
+    let bases: Vec<Base> = (0..len)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            match x % 4 {
+                0 => Base::I,
+                1 => Base::C,
+                2 => Base::F,
+                _ => Base::P,
+            }
+        })
+        .collect();
+    Dna::from_bases(&bases)
+}
+
+fn search(c: &mut Criterion) {
+    let mut group = c.benchmark_group("search");
+    let tests = [
+        ("test1", include_str!("../../dna/test1.dna")),
+        ("test2", include_str!("../../dna/test2.dna")),
+        ("test3", include_str!("../../dna/test3.dna")),
+    ];
+    for (name, src) in tests.iter() {
+        let hay: Dna = src.trim().parse().unwrap();
+        let needle: Vec<Base> = hay.iter_at(hay.len() - 4).collect();
+        group.bench_with_input(BenchmarkId::new("naive", name), &hay, |b, hay| {
+            b.iter(|| naive_find(hay, &needle, 0))
+        });
+        group.bench_with_input(BenchmarkId::new("kmp", name), &hay, |b, hay| {
+            b.iter(|| hay.find(&needle, 0))
+        });
+    }
+
+    group.sample_size(10);
+    let hay = endo_sized(7_500_000);
+    // a needle from the very end, so the whole haystack is scanned
+    let needle: Vec<Base> = hay.iter_at(hay.len() - 24).collect();
+    assert_eq!(naive_find(&hay, &needle, 0), hay.find(&needle, 0));
+    group.bench_with_input(BenchmarkId::new("naive", "endo-sized"), &hay, |b, hay| {
+        b.iter(|| naive_find(hay, &needle, 0))
+    });
+    group.bench_with_input(BenchmarkId::new("kmp", "endo-sized"), &hay, |b, hay| {
+        b.iter(|| hay.find(&needle, 0))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, search);
+criterion_main!(benches);
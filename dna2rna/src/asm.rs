@@ -0,0 +1,295 @@
+//! Assembles DNA from patterns and templates written out as text.
+//!
+//! Each line is either an instruction, `pattern / template`, or one of the
+//! directives `.dna ICFP`, which copies bases verbatim, and `.nat n`, which
+//! writes `n` as a number. Everything after a `;` is a comment.
+//!
+//! Items are separated by whitespace and written as in `disasm`, with a few
+//! additions. Runs of bases like `ICFP` are one item per base, `#n` stands
+//! for the bases of the number `n`, `\n` is `\n_0`, and `@ICFPICF` emits an
+//! RNA command wherever it appears.
+
+use crate::{Base, Dna, Dna2Rna};
+use std::error::Error;
+use std::fmt;
+
+/// Errors in assembler source, `line` counts from 1.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum AsmError {
+    /// Something that isn't an item here, or a directive with a bad argument.
+    UnknownItem { line: usize, item: String },
+    /// An instruction without the `/` between pattern and template.
+    MissingTemplate { line: usize },
+    /// A pattern whose `(` and `)` don't pair up.
+    UnbalancedGroup { line: usize },
+    /// A base right after a search, which the search would swallow.
+    BaseAfterSearch { line: usize },
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsmError::UnknownItem { line, item } => {
+                write!(f, "line {}: unknown item {:?}", line, item)
+            }
+            AsmError::MissingTemplate { line } => write!(f, "line {}: missing template", line),
+            AsmError::UnbalancedGroup { line } => write!(f, "line {}: unbalanced group", line),
+            AsmError::BaseAfterSearch { line } => {
+                write!(f, "line {}: base directly after search", line)
+            }
+        }
+    }
+}
+
+impl Error for AsmError {}
+
+fn parse_bases(s: &str) -> Option<Vec<Base>> {
+    s.chars().map(Base::from_char).collect()
+}
+
+fn quote(bases: &[Base], out: &mut Vec<Base>) {
+    out.extend(Dna2Rna::protect(1, &Dna::from_bases(bases)).unwrap().iter());
+}
+
+fn nat(n: usize, out: &mut Vec<Base>) {
+    out.extend(Dna2Rna::asnat(n).iter());
+}
+
+struct Line<'s> {
+    line: usize,
+    out: &'s mut Vec<Base>,
+}
+
+impl<'s> Line<'s> {
+    fn unknown(&self, item: &str) -> AsmError {
+        AsmError::UnknownItem {
+            line: self.line,
+            item: item.to_string(),
+        }
+    }
+
+    fn number(&self, item: &str, digits: &str) -> Result<usize, AsmError> {
+        digits.parse().map_err(|_| self.unknown(item))
+    }
+
+    /// Handles the items that mean the same in patterns and templates.
+    fn common(&mut self, item: &str) -> Result<Option<Vec<Base>>, AsmError> {
+        if let Some(bases) = parse_bases(item) {
+            return Ok(Some(bases));
+        }
+        if let Some(n) = item.strip_prefix('#') {
+            let mut bases = vec![];
+            nat(self.number(item, n)?, &mut bases);
+            return Ok(Some(bases));
+        }
+        if let Some(rna) = item.strip_prefix('@') {
+            match parse_bases(rna) {
+                Some(rna) if rna.len() == 7 => {
+                    self.out.extend_from_slice(&[Base::I, Base::I, Base::I]);
+                    self.out.extend_from_slice(&rna);
+                    return Ok(Some(vec![]));
+                }
+                _ => return Err(self.unknown(item)),
+            }
+        }
+        Ok(None)
+    }
+
+    fn pattern(&mut self, items: &[&str]) -> Result<(), AsmError> {
+        let mut level = 0;
+        let mut after_search = false;
+        for item in items {
+            let searched = after_search;
+            after_search = false;
+            if let Some(bases) = self.common(item)? {
+                if searched && !bases.is_empty() {
+                    return Err(AsmError::BaseAfterSearch { line: self.line });
+                }
+                quote(&bases, self.out);
+            } else if *item == "(" {
+                level += 1;
+                self.out.extend_from_slice(&[Base::I, Base::I, Base::P]);
+            } else if *item == ")" {
+                if level == 0 {
+                    return Err(AsmError::UnbalancedGroup { line: self.line });
+                }
+                level -= 1;
+                self.out.extend_from_slice(&[Base::I, Base::I, Base::C]);
+            } else if let Some(n) = item.strip_prefix('!') {
+                self.out.extend_from_slice(&[Base::I, Base::P]);
+                nat(self.number(item, n)?, self.out);
+            } else if let Some(s) = item.strip_prefix('?') {
+                let s = parse_bases(s).ok_or_else(|| self.unknown(item))?;
+                self.out.extend_from_slice(&[Base::I, Base::F, Base::F]);
+                quote(&s, self.out);
+                after_search = true;
+            } else {
+                return Err(self.unknown(item));
+            }
+        }
+        if level != 0 {
+            return Err(AsmError::UnbalancedGroup { line: self.line });
+        }
+        self.out.extend_from_slice(&[Base::I, Base::I, Base::C]);
+        Ok(())
+    }
+
+    fn template(&mut self, items: &[&str]) -> Result<(), AsmError> {
+        for item in items {
+            if let Some(bases) = self.common(item)? {
+                quote(&bases, self.out);
+            } else if let Some(r) = item.strip_prefix('\\') {
+                let (n, l) = match r.find('_') {
+                    Some(i) => (&r[..i], &r[i + 1..]),
+                    None => (r, "0"),
+                };
+                let (n, l) = (self.number(item, n)?, self.number(item, l)?);
+                self.out.extend_from_slice(&[Base::I, Base::F]);
+                nat(l, self.out);
+                nat(n, self.out);
+            } else if item.len() > 2 && item.starts_with('|') && item.ends_with('|') {
+                let n = self.number(item, &item[1..item.len() - 1])?;
+                self.out.extend_from_slice(&[Base::I, Base::I, Base::P]);
+                nat(n, self.out);
+            } else {
+                return Err(self.unknown(item));
+            }
+        }
+        self.out.extend_from_slice(&[Base::I, Base::I, Base::C]);
+        Ok(())
+    }
+}
+
+/// Assembles `src` into DNA.
+pub fn assemble(src: &str) -> Result<Dna, AsmError> {
+    let mut out = vec![];
+    for (i, text) in src.lines().enumerate() {
+        let text = match text.find(';') {
+            Some(c) => &text[..c],
+            None => text,
+        };
+        let items: Vec<&str> = text.split_whitespace().collect();
+        let mut line = Line {
+            line: i + 1,
+            out: &mut out,
+        };
+        match items.first() {
+            None => {}
+            Some(&".dna") => {
+                for item in &items[1..] {
+                    let bases = parse_bases(item).ok_or_else(|| line.unknown(item))?;
+                    line.out.extend(bases);
+                }
+            }
+            Some(&".nat") => {
+                for item in &items[1..] {
+                    let n = line.number(item, item)?;
+                    nat(n, line.out);
+                }
+            }
+            Some(_) => {
+                let slash = items
+                    .iter()
+                    .position(|item| *item == "/")
+                    .ok_or(AsmError::MissingTemplate { line: i + 1 })?;
+                line.pattern(&items[..slash])?;
+                line.template(&items[slash + 1..])?;
+            }
+        }
+    }
+    Ok(Dna::from_bases(&out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PItem, RnaCommand, TItem, VecRnaStore};
+
+    #[test]
+    fn round_trip() {
+        let src = "
+            ; the example step from the spec
+            ( !2 ) P / P I \\0_0
+            I ?ICFP ( #5 CF ) / \\1_2 |0| ICFP @PIPIIIC
+            !0 ?P / #3
+            .dna CFP
+            .nat 6
+        ";
+        let dna = assemble(src).unwrap();
+        let mut rna = VecRnaStore::new();
+        let mut d = Dna2Rna::new(&mut rna);
+        d.dna = dna;
+        assert_eq!(
+            d.pattern(),
+            Ok(Some(vec![
+                PItem::Open,
+                PItem::Skip(2),
+                PItem::Close,
+                PItem::Base(Base::P)
+            ]))
+        );
+        assert_eq!(
+            d.template(),
+            Ok(Some(vec![
+                TItem::Base(Base::P),
+                TItem::Base(Base::I),
+                TItem::Ref(0, 0)
+            ]))
+        );
+        let nat5: Vec<PItem> = Dna2Rna::asnat(5).iter().map(PItem::Base).collect();
+        let mut p = vec![
+            PItem::Base(Base::I),
+            PItem::Search(vec![Base::I, Base::C, Base::F, Base::P]),
+            PItem::Open,
+        ];
+        p.extend(nat5);
+        p.extend(vec![
+            PItem::Base(Base::C),
+            PItem::Base(Base::F),
+            PItem::Close,
+        ]);
+        assert_eq!(d.pattern(), Ok(Some(p)));
+        let t: Vec<TItem> = vec![TItem::Ref(1, 2), TItem::RefLen(0)]
+            .into_iter()
+            .chain("ICFP".parse::<Dna>().unwrap().iter().map(TItem::Base))
+            .collect();
+        assert_eq!(d.template(), Ok(Some(t)));
+        assert_eq!(
+            d.pattern(),
+            Ok(Some(vec![PItem::Skip(0), PItem::Search(vec![Base::P])]))
+        );
+        let nat3: Vec<TItem> = Dna2Rna::asnat(3).iter().map(TItem::Base).collect();
+        assert_eq!(d.template(), Ok(Some(nat3)));
+        assert_eq!(d.dna.to_string(), "CFPICCP");
+        drop(d);
+        assert_eq!(rna.rna, vec![RnaCommand::AddBlack]);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            assemble("I /\nP Q / I"),
+            Err(AsmError::UnknownItem {
+                line: 2,
+                item: "Q".to_string()
+            })
+        );
+        assert_eq!(assemble("I C"), Err(AsmError::MissingTemplate { line: 1 }));
+        assert_eq!(
+            assemble("( ( ) /"),
+            Err(AsmError::UnbalancedGroup { line: 1 })
+        );
+        assert_eq!(assemble(") /"), Err(AsmError::UnbalancedGroup { line: 1 }));
+        assert_eq!(
+            assemble("?IC P /"),
+            Err(AsmError::BaseAfterSearch { line: 1 })
+        );
+        assert_eq!(
+            assemble("/ @ICF"),
+            Err(AsmError::UnknownItem {
+                line: 1,
+                item: "@ICF".to_string()
+            })
+        );
+    }
+}
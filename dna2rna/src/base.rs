@@ -0,0 +1,48 @@
+use std::fmt;
+
+/// One of the four DNA bases.
+///
+/// The discriminants are the 2-bit codes used by the packed `Dna` storage.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Base {
+    I = 0,
+    C = 1,
+    F = 2,
+    P = 3,
+}
+
+impl Base {
+    pub fn from_char(c: char) -> Option<Base> {
+        match c {
+            'I' => Some(Base::I),
+            'C' => Some(Base::C),
+            'F' => Some(Base::F),
+            'P' => Some(Base::P),
+            _ => None,
+        }
+    }
+
+    pub fn to_char(self) -> char {
+        match self {
+            Base::I => 'I',
+            Base::C => 'C',
+            Base::F => 'F',
+            Base::P => 'P',
+        }
+    }
+
+    pub(crate) fn from_bits(bits: u8) -> Base {
+        match bits & 3 {
+            0 => Base::I,
+            1 => Base::C,
+            2 => Base::F,
+            _ => Base::P,
+        }
+    }
+}
+
+impl fmt::Display for Base {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_char())
+    }
+}
@@ -0,0 +1,195 @@
+//! Saved interpreter state.
+//!
+//! A `Snapshot` shares the DNA tree with the interpreter, so taking one is
+//! O(1) and the same snapshot can be restored any number of times. A
+//! `Checkpoint` adds the RNA emitted so far and can be written to disk. The
+//! origins of the loaded bases aren't saved, see `Dna2Rna::origin`.
+//!
+//! On disk a checkpoint is the `DNACKPT1` magic, the five `StepStats`
+//! counters, the DNA length and bases, and the RNA command count and the
+//! bases of all commands in order. Numbers are LEB128 varints and bases are
+//! packed four to a byte, first base in the low bits.
+
+use crate::trace::{push_bases, push_varint};
+use crate::{Base, Dna, RnaCommand, StepStats, RNA_LEN};
+use std::io::{self, Read, Write};
+
+/// The DNA and step counts of a `Dna2Rna`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Snapshot {
+    pub dna: Dna,
+    pub stats: StepStats,
+}
+
+/// A snapshot together with the RNA emitted up to it.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Checkpoint {
+    pub snapshot: Snapshot,
+    pub rna: Vec<RnaCommand>,
+}
+
+pub const CHECKPOINT_MAGIC: &[u8] = b"DNACKPT1";
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what)
+}
+
+struct Reader<'b> {
+    buf: &'b [u8],
+    pos: usize,
+}
+
+impl<'b> Reader<'b> {
+    /// Reads a varint, failing if it doesn't fit in a `usize`.
+    fn varint(&mut self) -> io::Result<usize> {
+        let mut n: usize = 0;
+        let mut shift = 0;
+        loop {
+            let byte = *self
+                .buf
+                .get(self.pos)
+                .ok_or_else(|| invalid("checkpoint ends inside a number"))?;
+            self.pos += 1;
+            let bits = (byte & 0x7f) as usize;
+            // anything shifted out of the top would be lost
+            if shift >= usize::BITS as usize || (bits << shift) >> shift != bits {
+                return Err(invalid("number in checkpoint is too large"));
+            }
+            n |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+            shift += 7;
+        }
+    }
+
+    fn bases(&mut self, len: usize) -> io::Result<Vec<Base>> {
+        let bytes = len.div_ceil(4);
+        if self.buf.len() - self.pos < bytes {
+            return Err(invalid("checkpoint ends inside the bases"));
+        }
+        let data = &self.buf[self.pos..self.pos + bytes];
+        self.pos += bytes;
+        Ok((0..len)
+            .map(|i| Base::from_bits(data[i / 4] >> (2 * (i % 4))))
+            .collect())
+    }
+}
+
+impl Checkpoint {
+    pub fn write_to<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let stats = &self.snapshot.stats;
+        let mut buf = CHECKPOINT_MAGIC.to_vec();
+        for n in &[
+            stats.matched,
+            stats.mismatched,
+            stats.finished,
+            stats.truncated_pattern,
+            stats.truncated_template,
+        ] {
+            push_varint(&mut buf, *n);
+        }
+        push_varint(&mut buf, self.snapshot.dna.len());
+        push_bases(&mut buf, self.snapshot.dna.iter());
+        push_varint(&mut buf, self.rna.len());
+        push_bases(&mut buf, self.rna.iter().flat_map(|r| r.bases().to_vec()));
+        out.write_all(&buf)?;
+        out.flush()
+    }
+
+    pub fn read_from<R: Read>(mut input: R) -> io::Result<Checkpoint> {
+        let mut buf = vec![];
+        input.read_to_end(&mut buf)?;
+        if !buf.starts_with(CHECKPOINT_MAGIC) {
+            return Err(invalid("not a checkpoint"));
+        }
+        let mut r = Reader {
+            buf: &buf,
+            pos: CHECKPOINT_MAGIC.len(),
+        };
+        let stats = StepStats {
+            matched: r.varint()?,
+            mismatched: r.varint()?,
+            finished: r.varint()?,
+            truncated_pattern: r.varint()?,
+            truncated_template: r.varint()?,
+        };
+        let dna_len = r.varint()?;
+        let dna = Dna::from_bases(&r.bases(dna_len)?);
+        let rna_count = r.varint()?;
+        let rna_len = rna_count
+            .checked_mul(RNA_LEN)
+            .ok_or_else(|| invalid("too many rna commands"))?;
+        let rna = r
+            .bases(rna_len)?
+            .chunks(RNA_LEN)
+            .map(|c| {
+                let mut bases = [Base::I; RNA_LEN];
+                bases.copy_from_slice(c);
+                RnaCommand::from_bases(bases)
+            })
+            .collect();
+        if r.pos != buf.len() {
+            return Err(invalid("trailing data after checkpoint"));
+        }
+        Ok(Checkpoint {
+            snapshot: Snapshot { dna, stats },
+            rna,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let dna: Dna = "ICFPPFCIICFPIC".parse().unwrap();
+        let checkpoint = Checkpoint {
+            snapshot: Snapshot {
+                dna: dna.slice(1..),
+                stats: StepStats {
+                    matched: 300,
+                    mismatched: 2,
+                    finished: 0,
+                    truncated_pattern: 0,
+                    truncated_template: 1,
+                },
+            },
+            rna: vec![RnaCommand::AddBlack, "CFPICFP".parse().unwrap()],
+        };
+        let mut buf = vec![];
+        checkpoint.write_to(&mut buf).unwrap();
+        assert_eq!(buf.len(), CHECKPOINT_MAGIC.len() + 6 + 5 + 1 + 4);
+        assert_eq!(Checkpoint::read_from(&buf[..]).unwrap(), checkpoint);
+        assert_eq!(
+            Checkpoint::read_from(&buf[..buf.len() - 1])
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(
+            Checkpoint::read_from(&b"DNATRACE"[..]).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn big_numbers() {
+        let mut buf = vec![];
+        push_varint(&mut buf, usize::MAX);
+        let mut r = Reader { buf: &buf, pos: 0 };
+        assert_eq!(r.varint().unwrap(), usize::MAX);
+        // one bit too many in the last byte, then a byte too many
+        let last = buf.len() - 1;
+        buf[last] |= 0x02;
+        let mut longer = buf.clone();
+        longer[last] = 0x80;
+        longer.push(0x01);
+        for bad in &[buf, longer] {
+            let mut r = Reader { buf: bad, pos: 0 };
+            assert_eq!(r.varint().unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
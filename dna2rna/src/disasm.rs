@@ -0,0 +1,123 @@
+//! Decodes DNA into patterns and templates without running it.
+//!
+//! Items are written in the notation of the spec: bases as themselves, `!n`
+//! for a skip, `?ICFP` for a search, `(` and `)` for groups, `\n_l` for a
+//! reference to environment `n` at protection level `l` and `|n|` for the
+//! length of environment `n`.
+
+use crate::{Dna, Dna2Rna, Dna2RnaError, PItem, RnaCommand, TItem, VecRnaStore};
+use std::fmt;
+
+impl fmt::Display for PItem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PItem::Base(b) => write!(f, "{}", b.to_char()),
+            PItem::Skip(n) => write!(f, "!{}", n),
+            PItem::Search(s) => {
+                let s: String = s.iter().map(|b| b.to_char()).collect();
+                write!(f, "?{}", s)
+            }
+            PItem::Open => f.write_str("("),
+            PItem::Close => f.write_str(")"),
+        }
+    }
+}
+
+impl fmt::Display for TItem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TItem::Base(b) => write!(f, "{}", b.to_char()),
+            TItem::Ref(n, l) => write!(f, "\\{}_{}", n, l),
+            TItem::RefLen(n) => write!(f, "|{}|", n),
+        }
+    }
+}
+
+/// One decoded pattern and template.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Instruction {
+    /// Offset of the pattern from the start of the disassembled DNA.
+    pub offset: usize,
+    /// Number of bases the pattern and template take up.
+    pub len: usize,
+    pub pattern: Vec<PItem>,
+    pub template: Vec<TItem>,
+    /// RNA commands found while decoding.
+    pub rna: Vec<RnaCommand>,
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pattern: Vec<String> = self.pattern.iter().map(|p| p.to_string()).collect();
+        let template: Vec<String> = self.template.iter().map(|t| t.to_string()).collect();
+        write!(
+            f,
+            "{:8}: {} / {}",
+            self.offset,
+            pattern.join(" "),
+            template.join(" ")
+        )?;
+        for r in &self.rna {
+            write!(f, " ; rna {}", r)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes `dna` as a sequence of patterns and templates, stopping when it
+/// runs out. A pattern or template cut short by the end is left out.
+pub fn disassemble(dna: &Dna) -> Result<Vec<Instruction>, Dna2RnaError> {
+    let mut instructions = vec![];
+    let mut rest = dna.clone();
+    let mut offset = 0;
+    while !rest.is_empty() {
+        let mut rna = VecRnaStore::new();
+        let mut d = Dna2Rna::new(&mut rna);
+        d.dna = rest;
+        let pattern = match d.pattern().map_err(|e| e.offset_by(offset))? {
+            Some(p) => p,
+            None => break,
+        };
+        let consumed = dna.len() - offset - d.dna.len();
+        let template = match d.template().map_err(|e| e.offset_by(offset + consumed))? {
+            Some(t) => t,
+            None => break,
+        };
+        rest = d.dna;
+        let len = dna.len() - offset - rest.len();
+        instructions.push(Instruction {
+            offset,
+            len,
+            pattern,
+            template,
+            rna: rna.rna,
+        });
+        offset += len;
+    }
+    Ok(instructions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listing() {
+        let dna: Dna = "IIPIPICPIICICIIFICCIFPPIICIIIPIPIIICCIIPIPICPIICIICIIPCPFIIFCFP"
+            .parse()
+            .unwrap();
+        // the trailing CFP is a pattern cut short by the end
+        let listing: Vec<String> = disassemble(&dna)
+            .unwrap()
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        assert_eq!(
+            listing,
+            vec![
+                "       0: ( !2 ) P / P I \\0_0",
+                "      26: I ( !2 ) / |1| C ; rna PIPIIIC",
+            ]
+        );
+    }
+}
@@ -0,0 +1,914 @@
+use crate::search::Kmp;
+use crate::{Base, Dna2RnaError};
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::{Bound, RangeBounds};
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+
+/// Maximum number of bases stored in a single leaf.
+const LEAF_MAX: usize = 1024;
+
+/// Number of I, C, F and P bases, indexed by `Base as usize`.
+type Counts = [usize; 4];
+
+/// An immutable sequence of bases, packed four to a byte.
+///
+/// The bases live in the leaves of an AVL-balanced tree whose nodes are
+/// shared between copies, so cloning is O(1) and `slice`, `split_off` and
+/// `append` are O(log n) without copying any bases.
+///
+/// `quoted` doesn't copy either: it wraps the tree in a node carrying the
+/// quote level, and the quoted bases are only produced when they are read.
+#[derive(Clone, Default)]
+pub struct Dna {
+    root: Option<Arc<Node>>,
+}
+
+enum Node {
+    Leaf {
+        data: Arc<[u8]>,
+        start: usize,
+        len: usize,
+        /// Never merged with its neighbours, so its pieces keep pointing at
+        /// `data`, which is how loaded DNA keeps its origin.
+        kept: bool,
+        counts: OnceLock<Counts>,
+    },
+    Branch {
+        left: Arc<Node>,
+        right: Arc<Node>,
+        len: usize,
+        height: usize,
+        counts: OnceLock<Counts>,
+    },
+    /// `child` quoted `level` times. Balancing treats it as a leaf.
+    Quoted {
+        child: Arc<Node>,
+        level: usize,
+        len: usize,
+        counts: Counts,
+    },
+}
+
+fn pack(bases: &[Base]) -> Arc<[u8]> {
+    let mut data = vec![0u8; bases.len().div_ceil(4)];
+    for (i, b) in bases.iter().enumerate() {
+        data[i / 4] |= (*b as u8) << (2 * (i % 4));
+    }
+    data.into()
+}
+
+fn unpack(data: &[u8], i: usize) -> Base {
+    Base::from_bits(data[i / 4] >> (2 * (i % 4)))
+}
+
+/// Base counts after quoting `level` times: I -> C, C -> F, F -> P, P -> IC.
+///
+/// `None` if that would be more bases than a `usize` can count.
+fn quote_counts(mut c: Counts, level: usize) -> Option<Counts> {
+    for _ in 0..level {
+        c = [c[3], c[0].checked_add(c[3])?, c[1], c[2]];
+    }
+    total(&c)?;
+    Some(c)
+}
+
+/// Number of bases in `c`, or `None` if it doesn't fit in a `usize`.
+fn total(c: &Counts) -> Option<usize> {
+    c.iter().try_fold(0usize, |n, c| n.checked_add(*c))
+}
+
+/// What `b` turns into when quoted once.
+fn quote_once(b: Base) -> &'static [Base] {
+    match b {
+        Base::I => &[Base::C],
+        Base::C => &[Base::F],
+        Base::F => &[Base::P],
+        Base::P => &[Base::I, Base::C],
+    }
+}
+
+/// How long each base gets when quoted up to `level` times.
+///
+/// Quoted bases are found by walking down one quote at a time with these,
+/// so a base's expansion never has to be built.
+#[derive(Clone)]
+struct Expansions {
+    /// `lens[k][b]` is the length of `b` quoted `k` times, saturating as
+    /// lengths that don't fit are never asked for.
+    lens: Vec<Counts>,
+}
+
+impl Expansions {
+    fn new(level: usize) -> Expansions {
+        let mut lens: Vec<Counts> = vec![[1; 4]];
+        for k in 0..level {
+            let l = lens[k];
+            lens.push([l[1], l[2], l[3], l[0].saturating_add(l[1])]);
+        }
+        Expansions { lens }
+    }
+
+    fn level(&self) -> usize {
+        self.lens.len() - 1
+    }
+
+    /// Length of `b` quoted `level` times.
+    fn len(&self, b: Base, level: usize) -> usize {
+        self.lens[level][b as usize]
+    }
+
+    /// Index in `data` of the base whose expansion at the top level holds
+    /// base `at` of the ones from `start` on, and where in there it is.
+    fn find(&self, data: &[u8], start: usize, mut at: usize) -> (usize, usize) {
+        let mut j = start;
+        loop {
+            let n = self.len(unpack(data, j), self.level());
+            if at < n {
+                return (j, at);
+            }
+            at -= n;
+            j += 1;
+        }
+    }
+}
+
+impl Node {
+    fn len(&self) -> usize {
+        match self {
+            Node::Leaf { len, .. } => *len,
+            Node::Branch { len, .. } => *len,
+            Node::Quoted { len, .. } => *len,
+        }
+    }
+
+    fn height(&self) -> usize {
+        match self {
+            Node::Branch { height, .. } => *height,
+            Node::Leaf { .. } | Node::Quoted { .. } => 0,
+        }
+    }
+
+    /// Whether this is a leaf, or a quoted leaf, that joining must not
+    /// merge: a piece of tracked DNA or of storage too big for one leaf.
+    fn is_kept(&self) -> bool {
+        match self {
+            Node::Leaf { data, kept, .. } => *kept || data.len() * 4 > LEAF_MAX,
+            Node::Quoted { child, .. } => child.is_kept(),
+            Node::Branch { .. } => false,
+        }
+    }
+
+    /// Base counts, computed on first use and cached in the (shared) node.
+    fn counts(&self) -> Counts {
+        match self {
+            Node::Leaf {
+                data,
+                start,
+                len,
+                counts,
+                ..
+            } => *counts.get_or_init(|| {
+                let mut c = [0; 4];
+                for i in *start..start + len {
+                    c[unpack(data, i) as usize] += 1;
+                }
+                c
+            }),
+            Node::Branch {
+                left,
+                right,
+                counts,
+                ..
+            } => *counts.get_or_init(|| {
+                let (l, r) = (left.counts(), right.counts());
+                [l[0] + r[0], l[1] + r[1], l[2] + r[2], l[3] + r[3]]
+            }),
+            Node::Quoted { counts, .. } => *counts,
+        }
+    }
+
+    /// Length of this node quoted `level` more times, which must fit.
+    fn quoted_len(&self, level: usize) -> usize {
+        if level == 0 {
+            self.len()
+        } else {
+            quote_counts(self.counts(), level)
+                .and_then(|c| total(&c))
+                .expect("quoted length overflows")
+        }
+    }
+
+    fn children(&self) -> (Arc<Node>, Arc<Node>) {
+        match self {
+            Node::Branch { left, right, .. } => (left.clone(), right.clone()),
+            _ => unreachable!("only branches have children"),
+        }
+    }
+
+    /// Builds a balanced tree over `len` bases of `data` starting at `start`.
+    fn build(data: &Arc<[u8]>, start: usize, len: usize, kept: bool) -> Arc<Node> {
+        if len <= LEAF_MAX {
+            return leaf(data, start, len, kept);
+        }
+        let leaves = len.div_ceil(LEAF_MAX);
+        let mid = (leaves / 2) * LEAF_MAX;
+        let left = Node::build(data, start, mid, kept);
+        let right = Node::build(data, start + mid, len - mid, kept);
+        branch(left, right)
+    }
+}
+
+fn leaf(data: &Arc<[u8]>, start: usize, len: usize, kept: bool) -> Arc<Node> {
+    Arc::new(Node::Leaf {
+        data: data.clone(),
+        start,
+        len,
+        kept,
+        counts: OnceLock::new(),
+    })
+}
+
+fn branch(left: Arc<Node>, right: Arc<Node>) -> Arc<Node> {
+    let len = left.len() + right.len();
+    let height = std::cmp::max(left.height(), right.height()) + 1;
+    Arc::new(Node::Branch {
+        left,
+        right,
+        len,
+        height,
+        counts: OnceLock::new(),
+    })
+}
+
+/// `node` quoted `level` times, or `None` if that's too long to address.
+fn quoted(node: Arc<Node>, level: usize) -> Option<Arc<Node>> {
+    if level == 0 {
+        return Some(node);
+    }
+    let (child, level) = match &*node {
+        Node::Quoted {
+            child, level: l, ..
+        } => (child.clone(), l.checked_add(level)?),
+        _ => (node, level),
+    };
+    let counts = quote_counts(child.counts(), level)?;
+    Some(Arc::new(Node::Quoted {
+        child,
+        level,
+        len: total(&counts)?,
+        counts,
+    }))
+}
+
+/// `quoted` for part of a node that is already quoted `level` times, which
+/// can't be any longer than the whole.
+fn quoted_part(node: Arc<Node>, level: usize) -> Arc<Node> {
+    quoted(node, level).expect("part of a quoted node is too long")
+}
+
+/// Splits `b` quoted `level` times at `at`, which must be inside it.
+fn split_base(exp: &Expansions, b: Base, level: usize, at: usize) -> Halves {
+    if at == 0 {
+        return (
+            None,
+            Some(quoted_part(leaf(&pack(&[b]), 0, 1, false), level)),
+        );
+    }
+    match quote_once(b) {
+        [q] => split_base(exp, *q, level - 1, at),
+        [first, second] => {
+            let whole = |b: Base| quoted_part(leaf(&pack(&[b]), 0, 1, false), level - 1);
+            let n = exp.len(*first, level - 1);
+            if at < n {
+                let (x, y) = split_base(exp, *first, level - 1, at);
+                (x, join_opt(y, Some(whole(*second))))
+            } else {
+                let (x, y) = split_base(exp, *second, level - 1, at - n);
+                (join_opt(Some(whole(*first)), x), y)
+            }
+        }
+        _ => unreachable!("quoting makes one or two bases"),
+    }
+}
+
+/// Joins two subtrees whose heights differ by at most two, rotating as needed.
+fn balance(left: Arc<Node>, right: Arc<Node>) -> Arc<Node> {
+    let (hl, hr) = (left.height(), right.height());
+    if hl > hr + 1 {
+        let (ll, lr) = left.children();
+        if ll.height() >= lr.height() {
+            branch(ll, branch(lr, right))
+        } else {
+            let (lrl, lrr) = lr.children();
+            branch(branch(ll, lrl), branch(lrr, right))
+        }
+    } else if hr > hl + 1 {
+        let (rl, rr) = right.children();
+        if rr.height() >= rl.height() {
+            branch(branch(left, rl), rr)
+        } else {
+            let (rll, rlr) = rl.children();
+            branch(branch(left, rll), branch(rlr, rr))
+        }
+    } else {
+        branch(left, right)
+    }
+}
+
+fn join(left: Arc<Node>, right: Arc<Node>) -> Arc<Node> {
+    let (hl, hr) = (left.height(), right.height());
+    if hl > hr + 1 {
+        let (ll, lr) = left.children();
+        balance(ll, join(lr, right))
+    } else if hr > hl + 1 {
+        let (rl, rr) = right.children();
+        balance(join(left, rl), rr)
+    } else if hl == 0
+        && hr == 0
+        && left.len() + right.len() <= LEAF_MAX
+        && !left.is_kept()
+        && !right.is_kept()
+    {
+        // merge small neighbours so template output doesn't fragment the tree,
+        // but keep pieces of tracked DNA where they are so they keep their source
+        let bases: Vec<Base> = Bases::new(&left, 0, 0)
+            .chain(Bases::new(&right, 0, 0))
+            .collect();
+        let data = pack(&bases);
+        Node::build(&data, 0, bases.len(), false)
+    } else {
+        branch(left, right)
+    }
+}
+
+fn join_opt(left: Option<Arc<Node>>, right: Option<Arc<Node>>) -> Option<Arc<Node>> {
+    match (left, right) {
+        (Some(l), Some(r)) => Some(join(l, r)),
+        (l, None) => l,
+        (None, r) => r,
+    }
+}
+
+type Halves = (Option<Arc<Node>>, Option<Arc<Node>>);
+
+/// Splits `node` quoted `level` times at `at`.
+fn split(node: &Arc<Node>, level: usize, at: usize) -> Halves {
+    if at == 0 {
+        return (None, Some(quoted_part(node.clone(), level)));
+    }
+    if at >= node.quoted_len(level) {
+        return (Some(quoted_part(node.clone(), level)), None);
+    }
+    match &**node {
+        Node::Leaf {
+            data,
+            start,
+            len,
+            kept,
+            ..
+        } => {
+            if level == 0 {
+                return (
+                    Some(leaf(data, *start, at, *kept)),
+                    Some(leaf(data, start + at, len - at, *kept)),
+                );
+            }
+            // find the base whose expansion straddles `at`
+            let exp = Expansions::new(level);
+            let (j, at) = exp.find(data, *start, at);
+            let j = j - start;
+            let (x, y) = split_base(&exp, unpack(data, start + j), level, at);
+            let head = if j > 0 {
+                Some(quoted_part(leaf(data, *start, j, *kept), level))
+            } else {
+                None
+            };
+            let tail = if j + 1 < *len {
+                Some(quoted_part(
+                    leaf(data, start + j + 1, len - j - 1, *kept),
+                    level,
+                ))
+            } else {
+                None
+            };
+            (join_opt(head, x), join_opt(y, tail))
+        }
+        Node::Branch { left, right, .. } => {
+            let llen = left.quoted_len(level);
+            if at < llen {
+                let (a, b) = split(left, level, at);
+                (a, join_opt(b, Some(quoted_part(right.clone(), level))))
+            } else {
+                let (a, b) = split(right, level, at - llen);
+                (join_opt(Some(quoted_part(left.clone(), level)), a), b)
+            }
+        }
+        Node::Quoted {
+            child, level: l, ..
+        } => split(child, level + l, at),
+    }
+}
+
+impl Dna {
+    pub fn new() -> Dna {
+        Dna { root: None }
+    }
+
+    pub fn from_bases(bases: &[Base]) -> Dna {
+        Dna::build(bases, false)
+    }
+
+    /// Like `from_bases`, but pieces of the result are never merged into
+    /// new storage when joined, so `storage` keeps finding them in there.
+    pub(crate) fn from_source(bases: &[Base]) -> Dna {
+        Dna::build(bases, true)
+    }
+
+    fn build(bases: &[Base], kept: bool) -> Dna {
+        if bases.is_empty() {
+            return Dna::new();
+        }
+        let data = pack(bases);
+        Dna {
+            root: Some(Node::build(&data, 0, bases.len(), kept)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.root.as_ref().map_or(0, |n| n.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    pub fn get(&self, i: usize) -> Option<Base> {
+        let mut node = self.root.as_ref()?;
+        let mut at = i;
+        let mut level = 0;
+        if at >= node.len() {
+            return None;
+        }
+        loop {
+            match &**node {
+                Node::Leaf { data, start, .. } if level == 0 => {
+                    return Some(unpack(data, start + at))
+                }
+                Node::Leaf { .. } => return Bases::new(node, level, at).next(),
+                Node::Branch { left, right, .. } => {
+                    let llen = left.quoted_len(level);
+                    if at < llen {
+                        node = left;
+                    } else {
+                        at -= llen;
+                        node = right;
+                    }
+                }
+                Node::Quoted {
+                    child, level: l, ..
+                } => {
+                    level += l;
+                    node = child;
+                }
+            }
+        }
+    }
+
+    /// The packed storage holding base `i`, or the base it was quoted from,
+    /// with its index in there and how many times it has been quoted.
+    pub(crate) fn storage(&self, i: usize) -> Option<(&Arc<[u8]>, usize, usize)> {
+        let mut node = self.root.as_ref()?;
+        let mut at = i;
+        let mut level = 0;
+        if at >= node.len() {
+            return None;
+        }
+        loop {
+            match &**node {
+                Node::Leaf { data, start, .. } => {
+                    if level == 0 {
+                        return Some((data, start + at, 0));
+                    }
+                    let (j, _) = Expansions::new(level).find(data, *start, at);
+                    return Some((data, j, level));
+                }
+                Node::Branch { left, right, .. } => {
+                    let llen = left.quoted_len(level);
+                    if at < llen {
+                        node = left;
+                    } else {
+                        at -= llen;
+                        node = right;
+                    }
+                }
+                Node::Quoted {
+                    child, level: l, ..
+                } => {
+                    level += l;
+                    node = child;
+                }
+            }
+        }
+    }
+
+    pub fn iter(&self) -> Bases<'_> {
+        self.iter_at(0)
+    }
+
+    /// Iterates over the bases starting at index `i`.
+    pub fn iter_at(&self, i: usize) -> Bases<'_> {
+        match &self.root {
+            Some(root) if i < root.len() => Bases::new(root, 0, i),
+            _ => Bases::empty(),
+        }
+    }
+
+    /// Returns the index of the first occurrence of `needle` at or after `from`.
+    pub fn find(&self, needle: &[Base], from: usize) -> Option<usize> {
+        if from > self.len() {
+            return None;
+        }
+        Kmp::new(needle)
+            .find_end(self.iter_at(from))
+            .map(|end| from + end - needle.len())
+    }
+
+    pub fn slice<R: RangeBounds<usize>>(&self, range: R) -> Dna {
+        let start = match range.start_bound() {
+            Bound::Included(s) => *s,
+            Bound::Excluded(s) => s + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(e) => e + 1,
+            Bound::Excluded(e) => *e,
+            Bound::Unbounded => self.len(),
+        };
+        let end = std::cmp::min(end, self.len());
+        if start >= end {
+            return Dna::new();
+        }
+        let root = self.root.as_ref().unwrap();
+        let (_, rest) = split(root, 0, start);
+        let (mid, _) = split(&rest.unwrap(), 0, end - start);
+        Dna { root: mid }
+    }
+
+    /// Truncates `self` to `[0, at)` and returns `[at, len)`.
+    pub fn split_off(&mut self, at: usize) -> Dna {
+        match self.root.take() {
+            Some(root) => {
+                let (a, b) = split(&root, 0, at);
+                self.root = a;
+                Dna { root: b }
+            }
+            None => Dna::new(),
+        }
+    }
+
+    pub fn append(&mut self, other: Dna) {
+        self.root = join_opt(self.root.take(), other.root);
+    }
+
+    /// Returns this DNA quoted `level` times without copying it, or `None`
+    /// if the result would be longer than `usize::MAX` bases.
+    ///
+    /// Only the base counts of the quoted subtrees are needed up front, and
+    /// those are cached in the shared nodes after the first time.
+    pub fn quoted(&self, level: usize) -> Option<Dna> {
+        let root = match &self.root {
+            Some(n) => Some(quoted(n.clone(), level)?),
+            None => None,
+        };
+        Some(Dna { root })
+    }
+}
+
+impl FromStr for Dna {
+    type Err = Dna2RnaError;
+
+    fn from_str(s: &str) -> Result<Dna, Dna2RnaError> {
+        Ok(Dna::from_bases(&parse_bases(s)?))
+    }
+}
+
+/// The bases spelled out by `s`, skipping ASCII whitespace.
+pub(crate) fn parse_bases(s: &str) -> Result<Vec<Base>, Dna2RnaError> {
+    let mut bases = Vec::with_capacity(s.len());
+    for (offset, c) in s.chars().enumerate() {
+        match Base::from_char(c) {
+            Some(b) => bases.push(b),
+            None if c.is_ascii_whitespace() => {}
+            None => return Err(Dna2RnaError::InvalidBase { offset, found: c }),
+        }
+    }
+    Ok(bases)
+}
+
+impl FromIterator<Base> for Dna {
+    fn from_iter<T: IntoIterator<Item = Base>>(iter: T) -> Dna {
+        let bases: Vec<Base> = iter.into_iter().collect();
+        Dna::from_bases(&bases)
+    }
+}
+
+impl PartialEq for Dna {
+    fn eq(&self, other: &Dna) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for Dna {}
+
+impl fmt::Display for Dna {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s: String = self.iter().map(Base::to_char).collect();
+        f.write_str(&s)
+    }
+}
+
+impl fmt::Debug for Dna {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Dna({})", self)
+    }
+}
+
+/// Iterator over the bases of a `Dna`, walking the leaves in order.
+#[derive(Clone)]
+pub struct Bases<'a> {
+    /// Right subtrees still to visit, with the quote level they're under.
+    stack: Vec<(&'a Node, usize)>,
+    data: &'a [u8],
+    pos: usize,
+    end: usize,
+    /// Expansions for the quote level of the current leaf, if it's quoted.
+    exp: Option<Expansions>,
+    /// Rest of the current quoted base's expansion, as bases still to be
+    /// quoted a number of times, the next one last.
+    pending: Vec<(Base, usize)>,
+}
+
+impl<'a> Bases<'a> {
+    fn empty() -> Bases<'a> {
+        Bases {
+            stack: vec![],
+            data: &[],
+            pos: 0,
+            end: 0,
+            exp: None,
+            pending: vec![],
+        }
+    }
+
+    fn new(node: &'a Node, level: usize, at: usize) -> Bases<'a> {
+        let mut bases = Bases::empty();
+        bases.descend(node, level, at);
+        bases
+    }
+
+    fn descend(&mut self, mut node: &'a Node, mut level: usize, mut at: usize) {
+        loop {
+            match node {
+                Node::Leaf {
+                    data, start, len, ..
+                } => {
+                    self.data = data;
+                    self.end = start + len;
+                    self.pending.clear();
+                    if level == 0 {
+                        self.exp = None;
+                        self.pos = start + at;
+                        return;
+                    }
+                    if self.exp.as_ref().map(|e| e.level()) != Some(level) {
+                        self.exp = Some(Expansions::new(level));
+                    }
+                    let (pos, at) = self.exp.as_ref().unwrap().find(data, *start, at);
+                    self.pos = pos + 1;
+                    self.expand(unpack(data, pos), level, at);
+                    return;
+                }
+                Node::Branch { left, right, .. } => {
+                    let llen = left.quoted_len(level);
+                    if at < llen {
+                        self.stack.push((right, level));
+                        node = left;
+                    } else {
+                        at -= llen;
+                        node = right;
+                    }
+                }
+                Node::Quoted {
+                    child, level: l, ..
+                } => {
+                    level += l;
+                    node = child;
+                }
+            }
+        }
+    }
+
+    /// Queues `b` quoted `level` times from its base `at` on.
+    fn expand(&mut self, mut b: Base, mut level: usize, mut at: usize) {
+        let exp = self.exp.as_ref().unwrap();
+        while level > 0 {
+            level -= 1;
+            let q = quote_once(b);
+            b = q[0];
+            if let Some(&second) = q.get(1) {
+                let n = exp.len(b, level);
+                if at < n {
+                    self.pending.push((second, level));
+                } else {
+                    at -= n;
+                    b = second;
+                }
+            }
+        }
+        self.pending.push((b, 0));
+    }
+}
+
+impl<'a> Iterator for Bases<'a> {
+    type Item = Base;
+
+    fn next(&mut self) -> Option<Base> {
+        loop {
+            if let Some((b, level)) = self.pending.pop() {
+                if level == 0 {
+                    return Some(b);
+                }
+                self.expand(b, level, 0);
+                continue;
+            }
+            if self.pos < self.end {
+                let b = unpack(self.data, self.pos);
+                self.pos += 1;
+                match &self.exp {
+                    None => return Some(b),
+                    Some(exp) => {
+                        let level = exp.level();
+                        self.expand(b, level, 0);
+                    }
+                }
+                continue;
+            }
+            let (node, level) = self.stack.pop()?;
+            self.descend(node, level, 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn height_ok(node: &Node) -> bool {
+        match node {
+            Node::Leaf { .. } | Node::Quoted { .. } => true,
+            Node::Branch { left, right, .. } => {
+                let (hl, hr) = (left.height() as isize, right.height() as isize);
+                (hl - hr).abs() <= 1 && height_ok(left) && height_ok(right)
+            }
+        }
+    }
+
+    fn balanced(d: &Dna) -> bool {
+        d.root.as_ref().is_none_or(|n| height_ok(n))
+    }
+
+    #[test]
+    fn parse() {
+        let d: Dna = "ICFPPFCI".parse().unwrap();
+        assert_eq!(d.len(), 8);
+        assert_eq!(d.to_string(), "ICFPPFCI");
+        assert_eq!(d.get(3), Some(Base::P));
+        assert_eq!(d.get(8), None);
+        assert_eq!("IC\nFP \n".parse::<Dna>().unwrap().to_string(), "ICFP");
+        assert_eq!(
+            "ICFx".parse::<Dna>(),
+            Err(Dna2RnaError::InvalidBase {
+                offset: 3,
+                found: 'x'
+            })
+        );
+    }
+
+    #[test]
+    fn split_and_append() {
+        let s: String = (0..10000)
+            .map(|i| Base::from_bits((i * 7 + i / 3) as u8).to_char())
+            .collect();
+        let mut d: Dna = s.parse().unwrap();
+        assert!(balanced(&d));
+        let tail = d.split_off(4321);
+        assert_eq!(d.to_string(), s[..4321]);
+        assert_eq!(tail.to_string(), s[4321..]);
+        assert_eq!(d.slice(1000..3000).to_string(), s[1000..3000]);
+        assert_eq!(
+            tail.iter_at(17).take(5000).collect::<Dna>().to_string(),
+            s[4338..9338]
+        );
+        // rebuild in many small pieces, the way templates are assembled
+        let mut r = Dna::new();
+        for i in (0..10000).step_by(7) {
+            let e = std::cmp::min(i + 7, 10000);
+            if e <= 4321 {
+                r.append(d.slice(i..e));
+            } else {
+                r.append(s[i..e].parse().unwrap());
+            }
+            assert!(balanced(&r));
+        }
+        assert_eq!(r.to_string(), s);
+        assert_eq!(r, s.parse().unwrap());
+    }
+
+    fn quote_eager(s: &str) -> String {
+        s.chars()
+            .map(|c| match c {
+                'I' => "C",
+                'C' => "F",
+                'F' => "P",
+                _ => "IC",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quoted() {
+        let s: String = (0..3000)
+            .map(|i| Base::from_bits((i * 5 + i / 11) as u8).to_char())
+            .collect();
+        let d: Dna = s.parse().unwrap();
+        let mut q = s.clone();
+        for level in 0..4 {
+            let dq = d.quoted(level).unwrap();
+            assert_eq!(dq.len(), q.len());
+            assert_eq!(dq.to_string(), q);
+            for at in (0..q.len()).step_by(97) {
+                assert_eq!(dq.get(at), Base::from_char(q.as_bytes()[at] as char));
+                assert_eq!(
+                    dq.iter_at(at).take(50).collect::<Dna>().to_string(),
+                    q[at..].chars().take(50).collect::<String>()
+                );
+                let mut head = dq.clone();
+                let tail = head.split_off(at);
+                assert_eq!(head.to_string(), q[..at]);
+                assert_eq!(tail.to_string(), q[at..]);
+            }
+            q = quote_eager(&q);
+        }
+        // quoting a mix of plain and already quoted parts
+        let mut m = d.slice(0..500).quoted(1).unwrap();
+        m.append(d.slice(500..1000));
+        let m2 = m.slice(100..900).quoted(2).unwrap();
+        let expected = quote_eager(&quote_eager(
+            &(quote_eager(&s[..500]) + &s[500..1000])[100..900],
+        ));
+        assert_eq!(m2.to_string(), expected);
+        assert_eq!(m2.len(), expected.len());
+        let needle: Vec<Base> = m2.iter_at(700).take(20).collect();
+        let needle_str: String = needle.iter().map(|b| b.to_char()).collect();
+        assert_eq!(m2.find(&needle, 0), expected.find(&needle_str));
+        assert!(balanced(&m2));
+    }
+
+    #[test]
+    fn deeply_quoted() {
+        let d: Dna = "ICFP".parse().unwrap();
+        let mut q = "ICFP".to_string();
+        for level in 0..12 {
+            let dq = d.quoted(level).unwrap();
+            for at in 0..q.len() {
+                assert_eq!(dq.get(at), Base::from_char(q.as_bytes()[at] as char));
+                assert_eq!(dq.iter_at(at).collect::<Dna>().to_string(), q[at..]);
+                let mut head = dq.clone();
+                let tail = head.split_off(at);
+                assert_eq!(head.to_string(), q[..at]);
+                assert_eq!(tail.to_string(), q[at..]);
+            }
+            q = quote_eager(&q);
+        }
+        // far too long to expand, but single bases can still be reached
+        let p: Dna = "P".parse().unwrap();
+        let level = (0..).take_while(|&l| p.quoted(l).is_some()).last().unwrap();
+        let mut dq = p.quoted(level).unwrap();
+        let len = dq.len();
+        assert!(len > usize::MAX / 2);
+        let mid = dq.iter_at(len / 2).take(10).collect::<Dna>();
+        assert_eq!(mid.len(), 10);
+        assert_eq!(dq.get(len / 2), mid.get(0));
+        assert_eq!(
+            dq.storage(len - 1).map(|(_, i, q)| (i, q)),
+            Some((0, level))
+        );
+        let tail = dq.split_off(len / 2);
+        assert_eq!(dq.len(), len / 2);
+        assert_eq!(tail.len(), len - len / 2);
+        assert_eq!(tail.iter().take(10).collect::<Dna>(), mid);
+        assert!(balanced(&tail));
+    }
+}
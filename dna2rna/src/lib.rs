@@ -1,13 +1,34 @@
 use log;
-use ropey::Rope;
 use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+pub mod asm;
+mod base;
+mod checkpoint;
+pub mod disasm;
+mod dna;
+pub mod profile;
+mod rna;
+mod search;
+mod trace;
+
+pub use base::Base;
+pub use checkpoint::{Checkpoint, Snapshot, CHECKPOINT_MAGIC};
+pub use dna::{Bases, Dna};
+pub use rna::{ParseRnaError, RnaCommand, RNA_LEN};
+pub use trace::{BinaryTracer, JsonLinesTracer, TraceStep, Tracer, BINARY_TRACE_MAGIC};
 
 pub trait RnaStore {
-    fn store(&mut self, rna: String);
+    fn store(&mut self, rna: RnaCommand);
 }
 
 pub struct VecRnaStore {
-    pub rna: Vec<String>,
+    pub rna: Vec<RnaCommand>,
 }
 
 impl VecRnaStore {
@@ -17,28 +38,322 @@ impl VecRnaStore {
 }
 
 impl RnaStore for VecRnaStore {
-    fn store(&mut self, rna: String) {
+    fn store(&mut self, rna: RnaCommand) {
         self.rna.push(rna);
     }
 }
 
+impl<T: RnaStore + ?Sized> RnaStore for &mut T {
+    fn store(&mut self, rna: RnaCommand) {
+        (**self).store(rna);
+    }
+}
+
+/// Stores nothing when `None`, so optional stores can be passed around.
+impl<T: RnaStore> RnaStore for Option<T> {
+    fn store(&mut self, rna: RnaCommand) {
+        if let Some(s) = self {
+            s.store(rna);
+        }
+    }
+}
+
+/// Writes RNA commands to `W` as they come, back to back like the `.rna`
+/// files `rna2fuun` reads.
+///
+/// Write errors stop the writing, the first one is returned by `finish`.
+pub struct WriterRnaStore<W: Write> {
+    out: io::BufWriter<W>,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> WriterRnaStore<W> {
+    pub fn new(out: W) -> WriterRnaStore<W> {
+        WriterRnaStore {
+            out: io::BufWriter::new(out),
+            error: None,
+        }
+    }
+
+    /// Flushes the output and returns it, or the first write error.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.out.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+impl<W: Write> RnaStore for WriterRnaStore<W> {
+    fn store(&mut self, rna: RnaCommand) {
+        if self.error.is_none() {
+            let mut buf = [0; RNA_LEN];
+            for (c, b) in buf.iter_mut().zip(rna.bases().iter()) {
+                *c = b.to_char() as u8;
+            }
+            self.error = self.out.write_all(&buf).err();
+        }
+    }
+}
+
+/// Hands every RNA command to both stores.
+pub struct TeeRnaStore<A: RnaStore, B: RnaStore> {
+    pub first: A,
+    pub second: B,
+}
+
+impl<A: RnaStore, B: RnaStore> TeeRnaStore<A, B> {
+    pub fn new(first: A, second: B) -> TeeRnaStore<A, B> {
+        TeeRnaStore { first, second }
+    }
+}
+
+impl<A: RnaStore, B: RnaStore> RnaStore for TeeRnaStore<A, B> {
+    fn store(&mut self, rna: RnaCommand) {
+        self.first.store(rna);
+        self.second.store(rna);
+    }
+}
+
+/// Callbacks for what the interpreter does, added with
+/// `Dna2Rna::add_observer`. Every callback does nothing by default.
+pub trait Dna2RnaObserver {
+    /// A step is starting with the pattern at `_source`, see
+    /// `Dna2Rna::source_offset`.
+    fn step_started(&mut self, _source: Option<usize>) {}
+
+    /// A step ended, its pattern and template took up `_consumed` bases.
+    fn step_finished(&mut self, _outcome: StepOutcome, _consumed: usize) {}
+
+    fn pattern_decoded(&mut self, _pattern: &[PItem]) {}
+
+    fn template_decoded(&mut self, _template: &[TItem]) {}
+
+    /// The pattern matched the first `_len` bases of the DNA.
+    fn matched(&mut self, _len: usize) {}
+
+    fn mismatched(&mut self) {}
+
+    /// Environment `_n` of a successful match was bound to `_env`.
+    fn env_captured(&mut self, _n: usize, _env: &Dna) {}
+
+    /// A step changed the length of the DNA.
+    fn dna_len_changed(&mut self, _old: usize, _new: usize) {}
+
+    /// `_origin` is where the first base of the command came from.
+    fn rna_emitted(&mut self, _rna: RnaCommand, _origin: Option<Origin>) {}
+}
+
+/// Errors raised while loading or executing DNA.
+///
+/// Offsets are counted in bases from the start of the DNA as it was at the
+/// beginning of the failing step, which a failing step leaves it as. Running
+/// the step again fails the same way, so recovering from an execution error
+/// means restoring a `Snapshot` taken before it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Dna2RnaError {
+    /// Something other than `I`, `C`, `F`, `P` or whitespace was found while
+    /// loading, `offset` is the character position in the prefix + DNA
+    /// input.
+    InvalidBase { offset: usize, found: char },
+    /// A pattern closes a group that was never opened, `item` is the index
+    /// of the offending close in the pattern.
+    UnbalancedClose { item: usize },
+    /// The DNA ended inside the RNA command starting at `offset`, which
+    /// was at `source` in the prefix + DNA input if it has an origin, see
+    /// `Dna2Rna::source_offset`.
+    PrematureEnd {
+        offset: usize,
+        source: Option<usize>,
+    },
+    /// A template would make the DNA longer than `usize::MAX` bases.
+    DnaTooLong,
+}
+
+impl Dna2RnaError {
+    fn offset_by(self, n: usize) -> Dna2RnaError {
+        match self {
+            Dna2RnaError::InvalidBase { offset, found } => Dna2RnaError::InvalidBase {
+                offset: offset + n,
+                found,
+            },
+            Dna2RnaError::PrematureEnd { offset, source } => Dna2RnaError::PrematureEnd {
+                offset: offset + n,
+                source,
+            },
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for Dna2RnaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Dna2RnaError::InvalidBase { offset, found } => {
+                write!(f, "invalid base {:?} at offset {}", found, offset)
+            }
+            Dna2RnaError::UnbalancedClose { item } => {
+                write!(f, "unbalanced close at pattern item {}", item)
+            }
+            Dna2RnaError::PrematureEnd { offset, source } => {
+                write!(f, "dna ended inside rna command at offset {}", offset)?;
+                match source {
+                    Some(s) => write!(f, " (input offset {})", s),
+                    None => Ok(()),
+                }
+            }
+            Dna2RnaError::DnaTooLong => write!(f, "dna grew too long"),
+        }
+    }
+}
+
+impl Error for Dna2RnaError {}
+
+/// What a single call to `Dna2Rna::execute_step` did.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StepOutcome {
+    /// The pattern matched and the DNA was rewritten from the template.
+    Matched,
+    /// The pattern did not match, only the pattern and template were consumed.
+    PatternMismatch,
+    /// The DNA was already empty.
+    Finished,
+    /// The DNA ran out while decoding the pattern.
+    TruncatedPattern,
+    /// The DNA ran out while decoding the template.
+    TruncatedTemplate,
+}
+
+impl StepOutcome {
+    /// Whether execution has ended, i.e. no further steps can be taken.
+    pub fn is_done(self) -> bool {
+        match self {
+            StepOutcome::Matched | StepOutcome::PatternMismatch => false,
+            StepOutcome::Finished
+            | StepOutcome::TruncatedPattern
+            | StepOutcome::TruncatedTemplate => true,
+        }
+    }
+}
+
+/// Tally of step outcomes since the DNA was last set.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct StepStats {
+    pub matched: usize,
+    pub mismatched: usize,
+    pub finished: usize,
+    pub truncated_pattern: usize,
+    pub truncated_template: usize,
+}
+
+impl StepStats {
+    pub fn steps(&self) -> usize {
+        self.matched
+            + self.mismatched
+            + self.finished
+            + self.truncated_pattern
+            + self.truncated_template
+    }
+
+    fn record(&mut self, outcome: StepOutcome) {
+        let count = match outcome {
+            StepOutcome::Matched => &mut self.matched,
+            StepOutcome::PatternMismatch => &mut self.mismatched,
+            StepOutcome::Finished => &mut self.finished,
+            StepOutcome::TruncatedPattern => &mut self.truncated_pattern,
+            StepOutcome::TruncatedTemplate => &mut self.truncated_template,
+        };
+        *count += 1;
+    }
+}
+
+/// Bounds on a call to `Dna2Rna::execute`, unset fields don't limit it.
+#[derive(Clone, Default, Debug)]
+pub struct Limits {
+    /// Steps taken by one call to `execute`.
+    pub max_steps: Option<usize>,
+    /// Length of the DNA between steps.
+    pub max_dna_len: Option<usize>,
+    pub deadline: Option<Instant>,
+    /// Stops execution once set, e.g. from another thread.
+    pub cancel: Option<Arc<AtomicBool>>,
+}
+
+/// The limit that stopped `Dna2Rna::execute`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Limit {
+    Steps,
+    DnaLength,
+    Deadline,
+    Cancelled,
+}
+
+impl Limits {
+    fn check(&self, steps: usize, dna_len: usize) -> Option<Limit> {
+        if let Some(cancel) = &self.cancel {
+            if cancel.load(Ordering::Relaxed) {
+                return Some(Limit::Cancelled);
+            }
+        }
+        if self.max_steps.is_some_and(|max| steps >= max) {
+            return Some(Limit::Steps);
+        }
+        if self.max_dna_len.is_some_and(|max| dna_len > max) {
+            return Some(Limit::DnaLength);
+        }
+        if self.deadline.is_some_and(|d| Instant::now() >= d) {
+            return Some(Limit::Deadline);
+        }
+        None
+    }
+}
+
+/// How a call to `Dna2Rna::execute` ended.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RunOutcome {
+    /// Execution ended, with the outcome of the last step.
+    Done(StepOutcome),
+    /// A limit was hit first, `execute` can be called again to carry on.
+    Limited(Limit),
+}
+
+/// Where a base came from, see `Dna2Rna::origin`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Origin {
+    /// Offset in bases in the prefix + DNA input, not counting whitespace.
+    pub offset: usize,
+    /// Times the base has been quoted since, each one turning it into one
+    /// or two other bases.
+    pub quotes: usize,
+}
+
 pub struct Dna2Rna<'a> {
-    dna: Rope,
+    dna: Dna,
     rna_store: &'a mut dyn RnaStore,
+    tracer: Option<&'a mut dyn Tracer>,
+    observers: Vec<&'a mut dyn Dna2RnaObserver>,
+    /// Storage of the loaded prefix and DNA, with the input offset of the
+    /// first base in each.
+    sources: Vec<(Arc<[u8]>, usize)>,
+    track_origins: bool,
+    stats: StepStats,
+    limits: Limits,
 }
 
+/// A decoded pattern item.
 #[derive(Clone, PartialEq, Eq, Debug)]
-enum PItem {
-    Base(char),
+pub enum PItem {
+    Base(Base),
     Skip(usize),
-    Search(String),
+    Search(Vec<Base>),
     Open,
     Close,
 }
 
+/// A decoded template item, `Ref` is environment number then level.
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
-enum TItem {
-    Base(char),
+pub enum TItem {
+    Base(Base),
     Ref(usize, usize),
     RefLen(usize),
 }
@@ -46,152 +361,390 @@ enum TItem {
 impl<'a> Dna2Rna<'a> {
     pub fn new(rna_store: &'a mut dyn RnaStore) -> Dna2Rna<'a> {
         Dna2Rna {
-            dna: Rope::new(),
+            dna: Dna::new(),
             rna_store,
+            tracer: None,
+            observers: vec![],
+            sources: vec![],
+            track_origins: false,
+            stats: StepStats::default(),
+            limits: Limits::default(),
         }
     }
 
-    pub fn set_dna_and_prefix(&mut self, dna_str: &str, prefix: Option<&str>) {
+    /// Notifies `observer` of everything from now on, after any observers
+    /// added before it.
+    pub fn add_observer(&mut self, observer: &'a mut dyn Dna2RnaObserver) {
+        self.observers.push(observer);
+    }
+
+    /// Hands every step from now on to `tracer`.
+    pub fn set_tracer(&mut self, tracer: &'a mut dyn Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Whether DNA loaded from now on remembers where its bases came from,
+    /// see `origin`. Off by default, as tracked pieces of the input are
+    /// never merged with their neighbours, which leaves the DNA in many
+    /// more small pieces.
+    pub fn set_track_origins(&mut self, track: bool) {
+        self.track_origins = track;
+    }
+
+    /// Loads `prefix` followed by `dna_str`, skipping whitespace and failing
+    /// on anything else that isn't one of the four bases.
+    pub fn set_dna_and_prefix(
+        &mut self,
+        dna_str: &str,
+        prefix: Option<&str>,
+    ) -> Result<(), Dna2RnaError> {
+        let load = if self.track_origins {
+            Dna::from_source
+        } else {
+            Dna::from_bases
+        };
         let mut dna = if let Some(p) = prefix {
-            Rope::from_str(&p)
+            load(&dna::parse_bases(p)?)
         } else {
-            Rope::new()
+            Dna::new()
         };
-        dna.append(Rope::from_str(dna_str));
+        let offset = dna.len();
+        let chars = prefix.map_or(0, |p| p.chars().count());
+        let body = dna::parse_bases(dna_str).map_err(|e| e.offset_by(chars))?;
+        let body = load(&body);
+        self.sources.clear();
+        if self.track_origins {
+            self.add_source(&dna, 0, 0);
+            self.add_source(&body, 0, offset);
+        }
+        dna.append(body);
         self.dna = dna;
+        self.stats = StepStats::default();
+        Ok(())
+    }
+
+    /// Notes the storage of base `at` of `dna`, which starts at `offset` in
+    /// the prefix + DNA input.
+    fn add_source(&mut self, dna: &Dna, at: usize, offset: usize) {
+        if let Some((data, i, _)) = dna.storage(at) {
+            if !self.sources.iter().any(|(d, _)| Arc::ptr_eq(d, data)) {
+                self.sources.push((data.clone(), offset + at - i));
+            }
+        }
+    }
+
+    /// Where the base at `i` came from in the prefix + DNA input.
+    ///
+    /// Only DNA loaded with `set_track_origins` on has origins. Bases that
+    /// template references copy or quote keep theirs, those written by
+    /// templates don't have one. Checkpoints don't save origins, so a run
+    /// resumed from one has none.
+    pub fn origin(&self, i: usize) -> Option<Origin> {
+        let (data, index, quotes) = self.dna.storage(i)?;
+        self.sources
+            .iter()
+            .find(|(d, _)| Arc::ptr_eq(d, data))
+            .map(|(_, offset)| Origin {
+                offset: offset + index,
+                quotes,
+            })
+    }
+
+    /// Where the base at `i` was in the prefix + DNA input, if it's still
+    /// unquoted, see `origin`.
+    pub fn source_offset(&self, i: usize) -> Option<usize> {
+        self.origin(i).filter(|o| o.quotes == 0).map(|o| o.offset)
+    }
+
+    pub fn stats(&self) -> &StepStats {
+        &self.stats
     }
 
-    pub fn execute(&mut self) {
-        log::info!("dna is {} bases long", self.dna.len_chars());
+    /// Captures the DNA and step counts. This doesn't copy the DNA.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            dna: self.dna.clone(),
+            stats: self.stats,
+        }
+    }
+
+    /// Goes back to `snapshot`. RNA already stored is left alone.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.dna = snapshot.dna.clone();
+        self.stats = snapshot.stats;
+    }
+
+    /// Sets the limits checked by `execute` between steps.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Runs steps until the DNA runs out or one of the limits is hit.
+    pub fn execute(&mut self) -> Result<RunOutcome, Dna2RnaError> {
+        log::info!("dna is {} bases long", self.dna.len());
         let mut i = 0;
-        loop {
-            if self.execute_step() {
-                break;
+        let outcome = loop {
+            if let Some(limit) = self.limits.check(i, self.dna.len()) {
+                break RunOutcome::Limited(limit);
+            }
+            let outcome = self.execute_step()?;
+            if outcome.is_done() {
+                break RunOutcome::Done(outcome);
             }
             i = i + 1;
             if i % 1000 == 0 {
-                log::info!("at step {}, dna: {}", i, self.dna.len_chars());
+                log::info!("at step {}, dna: {}", i, self.dna.len());
             }
-        }
+        };
+        log::info!("{:?} after {} steps: {:?}", outcome, i, self.stats);
         log::debug!("remaining dna: {:?}", self.dna);
+        Ok(outcome)
     }
 
-    pub fn execute_step(&mut self) -> bool {
-        if let Some(p) = self.pattern() {
-            if let Some(t) = self.template() {
-                self.match_replace(&p, &t);
-            } else {
-                return true;
+    /// Runs one pattern/template/match-replace iteration.
+    ///
+    /// If it fails the DNA is left as it was before the step, though any
+    /// RNA the step emitted first stays stored.
+    pub fn execute_step(&mut self) -> Result<StepOutcome, Dna2RnaError> {
+        let outcome = match self.tracer {
+            None => self.step(None)?,
+            Some(_) => {
+                let mut trace = TraceStep::new(self.stats.steps());
+                let outcome = self.step(Some(&mut trace))?;
+                trace.outcome = outcome;
+                trace.dna_len = self.dna.len();
+                if let Some(tracer) = self.tracer.as_mut() {
+                    tracer.trace(&trace);
+                }
+                outcome
             }
-        } else {
-            return true;
+        };
+        self.stats.record(outcome);
+        Ok(outcome)
+    }
+
+    fn step(&mut self, mut trace: Option<&mut TraceStep>) -> Result<StepOutcome, Dna2RnaError> {
+        let before = self.dna.clone();
+        let start = self.dna.len();
+        if !self.observers.is_empty() {
+            let source = self.source_offset(0);
+            for o in self.observers.iter_mut() {
+                o.step_started(source);
+            }
+        }
+        let (outcome, consumed) = match self.decode_and_match(start, trace.as_deref_mut()) {
+            Ok(done) => done,
+            Err(e) => {
+                self.dna = before;
+                return Err(self.with_source(e));
+            }
+        };
+        if let Some(trace) = trace {
+            trace.consumed = consumed;
+        }
+        for o in self.observers.iter_mut() {
+            o.step_finished(outcome, consumed);
         }
-        false
+        if self.dna.len() != start {
+            for o in self.observers.iter_mut() {
+                o.dna_len_changed(start, self.dna.len());
+            }
+        }
+        Ok(outcome)
     }
 
-    fn nat(mut chars: ropey::iter::Chars) -> Option<(usize, usize)> {
-        let mut bits = vec![];
+    /// `e` with the input offset of where it happened filled in, for the
+    /// DNA as it was at the start of the step.
+    fn with_source(&self, e: Dna2RnaError) -> Dna2RnaError {
+        match e {
+            Dna2RnaError::PrematureEnd { offset, .. } => Dna2RnaError::PrematureEnd {
+                offset,
+                source: self.source_offset(offset),
+            },
+            e => e,
+        }
+    }
+
+    /// Does the work of a step, returning the outcome and the number of
+    /// bases taken up by the pattern and template.
+    fn decode_and_match(
+        &mut self,
+        start: usize,
+        trace: Option<&mut TraceStep>,
+    ) -> Result<(StepOutcome, usize), Dna2RnaError> {
+        if start == 0 {
+            return Ok((StepOutcome::Finished, 0));
+        }
+        let p = match self.pattern()? {
+            Some(p) => p,
+            None => return Ok((StepOutcome::TruncatedPattern, start - self.dna.len())),
+        };
+        for o in self.observers.iter_mut() {
+            o.pattern_decoded(&p);
+        }
+        let consumed = start - self.dna.len();
+        let t = match self.template().map_err(|e| e.offset_by(consumed))? {
+            Some(t) => t,
+            None => {
+                if let Some(trace) = trace {
+                    trace.pattern = p;
+                }
+                return Ok((StepOutcome::TruncatedTemplate, start - self.dna.len()));
+            }
+        };
+        for o in self.observers.iter_mut() {
+            o.template_decoded(&t);
+        }
+        let consumed = start - self.dna.len();
+        let found = self.find_match(&p)?;
+        for o in self.observers.iter_mut() {
+            match &found {
+                Some((i, e)) => {
+                    o.matched(*i);
+                    for (n, env) in e.iter().enumerate() {
+                        o.env_captured(n, env);
+                    }
+                }
+                None => o.mismatched(),
+            }
+        }
+        if let Some(trace) = trace {
+            if let Some((i, e)) = &found {
+                trace.matched = Some(*i);
+                trace.envs = e.iter().map(|d| d.len()).collect();
+            }
+            trace.pattern = p;
+            trace.template = t.clone();
+        }
+        match found {
+            Some((i, e)) => {
+                self.dna = self.dna.slice(i..);
+                self.replace(&t, e)?;
+                Ok((StepOutcome::Matched, consumed))
+            }
+            None => Ok((StepOutcome::PatternMismatch, consumed)),
+        }
+    }
+
+    /// Decodes a number, least significant bit first, saturating at
+    /// `usize::MAX`.
+    ///
+    /// Numbers wider than a machine word are still consumed in full. A
+    /// saturated `Skip` can never match and a saturated `Ref` index never
+    /// names an environment, so saturating doesn't change the outcome. A
+    /// saturated protection level quotes an empty environment to nothing,
+    /// and any other to more bases than can be addressed, which fails the
+    /// step with `Dna2RnaError::DnaTooLong` as the exact level would.
+    fn nat<I: Iterator<Item = Base>>(mut bases: I) -> Option<(usize, usize)> {
+        let mut n: usize = 0;
         let mut consumed = 0;
         loop {
-            let c = chars.next()?;
-            consumed = consumed + 1;
-            match c {
-                'P' => break,
-                'I' | 'F' => bits.push(0),
-                'C' => bits.push(1),
-                _ => panic!(),
+            let b = bases.next()?;
+            match b {
+                Base::P => break,
+                Base::I | Base::F => {}
+                Base::C => {
+                    n = if consumed < usize::BITS as usize {
+                        n | 1 << consumed
+                    } else {
+                        usize::MAX
+                    };
+                }
             }
+            consumed += 1;
         }
-        let n = bits
-            .iter()
-            .enumerate()
-            .map(|(i, b)| b * 2usize.pow(i as u32))
-            .sum();
-        Some((n, consumed))
+        Some((n, consumed + 1))
     }
 
-    fn consts(mut chars: ropey::iter::Chars) -> (String, usize) {
+    fn consts<I: Iterator<Item = Base>>(mut bases: I) -> (Vec<Base>, usize) {
         let mut s = vec![];
         let mut extra = 0;
         loop {
-            if let Some(c) = chars.next() {
-                match c {
-                    'C' => s.push('I'),
-                    'F' => s.push('C'),
-                    'P' => s.push('F'),
-                    'I' => {
-                        if let Some(cc) = chars.next() {
-                            if cc == 'C' {
-                                extra = extra + 1;
-                                s.push('P');
-                            } else {
-                                break;
-                            }
+            if let Some(b) = bases.next() {
+                match b {
+                    Base::C => s.push(Base::I),
+                    Base::F => s.push(Base::C),
+                    Base::P => s.push(Base::F),
+                    Base::I => {
+                        if let Some(Base::C) = bases.next() {
+                            extra += 1;
+                            s.push(Base::P);
                         } else {
                             break;
                         }
                     }
-                    _ => {
-                        break;
-                    }
                 }
             } else {
                 break;
             }
         }
         let consumed = s.len() + extra;
-        let ret = s.into_iter().collect();
-        (ret, consumed)
+        (s, consumed)
     }
 
-    fn pattern(&mut self) -> Option<Vec<PItem>> {
+    fn pattern(&mut self) -> Result<Option<Vec<PItem>>, Dna2RnaError> {
         let mut p = vec![];
         let mut level = 0;
         let mut ret = false;
+        let mut pos = 0;
         while !ret {
-            let mut chars = self.dna.chars();
-            let first = chars.next()?;
+            let mut bases = self.dna.iter();
+            let first = match bases.next() {
+                Some(b) => b,
+                None => return Ok(None),
+            };
             let consumed = match first {
-                'C' => {
-                    p.push(PItem::Base('I'));
+                Base::C => {
+                    p.push(PItem::Base(Base::I));
                     1
                 }
-                'F' => {
-                    p.push(PItem::Base('C'));
+                Base::F => {
+                    p.push(PItem::Base(Base::C));
                     1
                 }
-                'P' => {
-                    p.push(PItem::Base('F'));
+                Base::P => {
+                    p.push(PItem::Base(Base::F));
                     1
                 }
-                'I' => {
-                    let second = chars.next()?;
-                    let consumed = match second {
-                        'C' => {
-                            p.push(PItem::Base('P'));
+                Base::I => {
+                    let second = match bases.next() {
+                        Some(b) => b,
+                        None => return Ok(None),
+                    };
+                    match second {
+                        Base::C => {
+                            p.push(PItem::Base(Base::P));
                             2
                         }
-                        'P' => {
-                            let (n, consumed) = Dna2Rna::nat(chars)?;
+                        Base::P => {
+                            let (n, consumed) = match Dna2Rna::nat(bases) {
+                                Some(nat) => nat,
+                                None => return Ok(None),
+                            };
                             p.push(PItem::Skip(n));
                             2 + consumed
                         }
-                        'F' => {
-                            let _ = chars.next()?;
-                            let (s, consumed) = Dna2Rna::consts(chars);
+                        Base::F => {
+                            if bases.next().is_none() {
+                                return Ok(None);
+                            }
+                            let (s, consumed) = Dna2Rna::consts(bases);
                             p.push(PItem::Search(s));
                             // yes, 3
                             3 + consumed
                         }
-                        'I' => {
-                            let third = chars.next()?;
-                            let consumed = match third {
-                                'P' => {
+                        Base::I => {
+                            let third = match bases.next() {
+                                Some(b) => b,
+                                None => return Ok(None),
+                            };
+                            match third {
+                                Base::P => {
                                     level = level + 1;
                                     p.push(PItem::Open);
                                     3
                                 }
-                                'C' | 'F' => {
+                                Base::C | Base::F => {
                                     if level == 0 {
                                         ret = true;
                                     } else {
@@ -200,235 +753,239 @@ impl<'a> Dna2Rna<'a> {
                                     }
                                     3
                                 }
-                                'I' => {
-                                    self.rna_store.store(self.dna.slice(3..10).into());
+                                Base::I => {
+                                    self.emit_rna(pos)?;
                                     10
                                 }
-                                _ => 0,
-                            };
-                            consumed
+                            }
                         }
-                        _ => 0,
-                    };
-                    consumed
+                    }
                 }
-                _ => 0,
             };
-            if consumed == 0 {
-                break;
-            }
             log::debug!(
                 "consuming dna: {} of available {}",
                 consumed,
-                self.dna.len_chars()
+                self.dna.len()
             );
             self.dna = self.dna.split_off(consumed);
+            pos += consumed;
         }
-        Some(p)
+        Ok(Some(p))
     }
 
-    fn template(&mut self) -> Option<Vec<TItem>> {
+    fn template(&mut self) -> Result<Option<Vec<TItem>>, Dna2RnaError> {
         let mut t = vec![];
         let mut ret = false;
+        let mut pos = 0;
         while !ret {
-            let mut chars = self.dna.chars();
-            let first = chars.next()?;
+            let mut bases = self.dna.iter();
+            let first = match bases.next() {
+                Some(b) => b,
+                None => return Ok(None),
+            };
             let consumed = match first {
-                'C' => {
-                    t.push(TItem::Base('I'));
+                Base::C => {
+                    t.push(TItem::Base(Base::I));
                     1
                 }
-                'F' => {
-                    t.push(TItem::Base('C'));
+                Base::F => {
+                    t.push(TItem::Base(Base::C));
                     1
                 }
-                'P' => {
-                    t.push(TItem::Base('F'));
+                Base::P => {
+                    t.push(TItem::Base(Base::F));
                     1
                 }
-                'I' => {
-                    let second = chars.next()?;
-                    let consumed = match second {
-                        'C' => {
-                            t.push(TItem::Base('P'));
+                Base::I => {
+                    let second = match bases.next() {
+                        Some(b) => b,
+                        None => return Ok(None),
+                    };
+                    match second {
+                        Base::C => {
+                            t.push(TItem::Base(Base::P));
                             2
                         }
-                        'F' | 'P' => {
-                            let (l, consumed) = Dna2Rna::nat(chars)?;
-                            let next_chars = self.dna.chars_at(2 + consumed);
-                            let (n, next_consumed) = Dna2Rna::nat(next_chars)?;
+                        Base::F | Base::P => {
+                            let (l, consumed) = match Dna2Rna::nat(&mut bases) {
+                                Some(nat) => nat,
+                                None => return Ok(None),
+                            };
+                            let (n, next_consumed) = match Dna2Rna::nat(&mut bases) {
+                                Some(nat) => nat,
+                                None => return Ok(None),
+                            };
                             t.push(TItem::Ref(n, l));
                             2 + consumed + next_consumed
                         }
-                        'I' => {
-                            let third = chars.next()?;
-                            let consumed = match third {
-                                'C' | 'F' => {
+                        Base::I => {
+                            let third = match bases.next() {
+                                Some(b) => b,
+                                None => return Ok(None),
+                            };
+                            match third {
+                                Base::C | Base::F => {
                                     ret = true;
                                     3
                                 }
-                                'P' => {
-                                    let (n, consumed) = Dna2Rna::nat(chars)?;
+                                Base::P => {
+                                    let (n, consumed) = match Dna2Rna::nat(bases) {
+                                        Some(nat) => nat,
+                                        None => return Ok(None),
+                                    };
                                     t.push(TItem::RefLen(n));
                                     3 + consumed
                                 }
-                                'I' => {
-                                    self.rna_store.store(self.dna.slice(3..10).into());
+                                Base::I => {
+                                    self.emit_rna(pos)?;
                                     10
                                 }
-                                _ => panic!(),
-                            };
-                            consumed
+                            }
                         }
-                        _ => panic!(),
-                    };
-                    consumed
+                    }
                 }
-                _ => panic!(),
             };
             self.dna = self.dna.split_off(consumed);
+            pos += consumed;
+        }
+        Ok(Some(t))
+    }
+
+    fn emit_rna(&mut self, pos: usize) -> Result<(), Dna2RnaError> {
+        if self.dna.len() < 10 {
+            return Err(Dna2RnaError::PrematureEnd {
+                offset: pos,
+                source: None,
+            });
         }
-        Some(t)
+        let mut bases = [Base::I; RNA_LEN];
+        for (b, d) in bases.iter_mut().zip(self.dna.slice(3..10).iter()) {
+            *b = d;
+        }
+        let rna = RnaCommand::from_bases(bases);
+        if !self.observers.is_empty() {
+            let origin = self.origin(3);
+            for o in self.observers.iter_mut() {
+                o.rna_emitted(rna, origin);
+            }
+        }
+        self.rna_store.store(rna);
+        Ok(())
     }
 
-    fn match_replace(&mut self, pattern: &[PItem], template: &[TItem]) {
+    /// Matches `pattern` against the DNA, returning the number of bases
+    /// matched and the environments bound.
+    fn find_match(&self, pattern: &[PItem]) -> Result<Option<(usize, Vec<Dna>)>, Dna2RnaError> {
         let mut e = vec![];
         let mut c = VecDeque::new();
         let mut i = 0;
-        log::debug!("match/replace: {:?} {:?}", pattern, template);
-        for p in pattern {
+        log::debug!("match: {:?}", pattern);
+        for (ix, p) in pattern.iter().enumerate() {
             log::debug!("pattern: {:?}", p);
             match p {
                 PItem::Base(b) => {
-                    if self.dna.char(i) == *b {
+                    if self.dna.get(i) == Some(*b) {
                         i = i + 1;
                     } else {
-                        log::debug!("no match for {}", b);
-                        return;
+                        log::debug!("no match for {:?}", b);
+                        return Ok(None);
                     }
                 }
                 PItem::Skip(n) => {
-                    if i + *n > self.dna.len_chars() {
+                    if *n > self.dna.len() - i {
                         log::debug!("can't skip {}", *n);
-                        return;
+                        return Ok(None);
                     }
                     i = i + *n;
                 }
-                PItem::Search(s) => {
-                    let mut n = i;
-                    let slen = s.len();
-                    let dlen = self.dna.len_chars();
-                    let hay = self.dna.slice(n..);
-                    let mut hay_iter = hay.chars();
-                    loop {
-                        if n + slen > dlen {
-                            return;
-                        }
-                        let h = hay_iter.clone();
-                        if h.take(slen).eq(s.chars()) {
-                            log::debug!("found {} at {}", s, n);
-                            i = n + slen;
-                            break;
-                        }
-                        hay_iter.next().unwrap();
-                        n = n + 1;
+                PItem::Search(s) => match self.dna.find(s, i) {
+                    Some(n) => {
+                        log::debug!("found {:?} at {}", s, n);
+                        i = n + s.len();
                     }
-                }
+                    None => return Ok(None),
+                },
                 PItem::Open => c.push_front(i),
                 PItem::Close => {
-                    let cval = c.pop_front().expect("malformed pattern");
-                    e.push(Rope::from(self.dna.slice(cval..i)));
+                    let cval = c
+                        .pop_front()
+                        .ok_or(Dna2RnaError::UnbalancedClose { item: ix })?;
+                    e.push(self.dna.slice(cval..i));
                 }
             }
         }
         log::debug!("dna = dna[{}..]", i);
-        if i < self.dna.len_chars() {
-            self.dna = self.dna.slice(i..).into();
-        } else {
-            self.dna = Rope::new();
-        }
-        self.replace(template, e);
+        Ok(Some((i, e)))
     }
 
-    fn replace(&mut self, template: &[TItem], e: Vec<Rope>) {
+    fn replace(&mut self, template: &[TItem], e: Vec<Dna>) -> Result<(), Dna2RnaError> {
         log::debug!("replacing {:?} with envs {:?}", template, e.len());
-        let mut r = Rope::new();
-        let mut bases = String::new();
+        let mut r = Dna::new();
+        let mut bases = vec![];
         for t in template {
             match t {
                 TItem::Base(b) => {
                     bases.push(*b);
                 }
                 TItem::Ref(n, l) => {
-                    if bases.len() > 0 {
-                        r.append(Rope::from(bases.as_str()));
+                    if !bases.is_empty() {
+                        Dna2Rna::append(&mut r, Dna::from_bases(&bases))?;
                         bases.clear();
                     }
                     if *n < e.len() {
-                        r.append(Dna2Rna::protect(*l, &e[*n]));
+                        let d = Dna2Rna::protect(*l, &e[*n]).ok_or(Dna2RnaError::DnaTooLong)?;
+                        Dna2Rna::append(&mut r, d)?;
                     }
                 }
                 TItem::RefLen(n) => {
-                    if bases.len() > 0 {
-                        r.append(Rope::from(bases.as_str()));
+                    if !bases.is_empty() {
+                        Dna2Rna::append(&mut r, Dna::from_bases(&bases))?;
                         bases.clear();
                     }
                     if *n < e.len() {
-                        r.append(Dna2Rna::asnat(e[*n].len_chars()));
+                        Dna2Rna::append(&mut r, Dna2Rna::asnat(e[*n].len()))?;
                     } else {
-                        r.append(Dna2Rna::asnat(0));
+                        Dna2Rna::append(&mut r, Dna2Rna::asnat(0))?;
                     }
                 }
             }
         }
-        if bases.len() > 0 {
-            r.append(Rope::from(bases.as_str()));
+        if !bases.is_empty() {
+            Dna2Rna::append(&mut r, Dna::from_bases(&bases))?;
             bases.clear();
         }
         std::mem::swap(&mut self.dna, &mut r);
-        self.dna.append(r);
+        Dna2Rna::append(&mut self.dna, r)
     }
 
-    fn protect(l: usize, d: &Rope) -> Rope {
-        if l == 0 {
-            d.clone()
-        } else {
-            Dna2Rna::protect(l - 1, &Dna2Rna::quote(d))
+    /// Appends `d` to `r`, unless the result would be too long to address.
+    fn append(r: &mut Dna, d: Dna) -> Result<(), Dna2RnaError> {
+        if r.len().checked_add(d.len()).is_none() {
+            return Err(Dna2RnaError::DnaTooLong);
         }
+        r.append(d);
+        Ok(())
     }
 
-    fn quote(d: &Rope) -> Rope {
-        let mut ret = String::new();
-        for c in d.chars() {
-            match c {
-                'I' => ret.push('C'),
-                'C' => ret.push('F'),
-                'F' => ret.push('P'),
-                'P' => {
-                    ret.push('I');
-                    ret.push('C');
-                }
-                _ => return Rope::new(),
-            }
-        }
-        Rope::from(ret)
+    fn protect(l: usize, d: &Dna) -> Option<Dna> {
+        d.quoted(l)
     }
 
-    fn asnat(mut n: usize) -> Rope {
-        let mut ret = String::new();
+    fn asnat(mut n: usize) -> Dna {
+        let mut ret = vec![];
         loop {
             if n == 0 {
-                ret.push('P');
+                ret.push(Base::P);
                 break;
             } else if n % 2 == 0 {
-                ret.push('I');
+                ret.push(Base::I);
                 n = n / 2;
             } else {
-                ret.push('C');
+                ret.push(Base::C);
                 n = n / 2;
             }
         }
-        Rope::from(ret)
+        Dna::from_bases(&ret)
     }
 }
 
@@ -440,70 +997,133 @@ mod tests {
         let _ = env_logger::builder().is_test(true).try_init();
     }
 
+    fn dna(s: &str) -> Dna {
+        s.parse().unwrap()
+    }
+
+    fn bases(s: &str) -> Vec<Base> {
+        dna(s).iter().collect()
+    }
+
+    /// Runs an already decoded pattern and template.
+    fn find_and_replace(
+        d: &mut Dna2Rna,
+        pattern: &[PItem],
+        template: &[TItem],
+    ) -> Result<bool, Dna2RnaError> {
+        match d.find_match(pattern)? {
+            Some((i, e)) => {
+                d.dna = d.dna.slice(i..);
+                d.replace(template, e)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
     #[test]
     fn pattern() {
         init();
         let mut rna_1 = VecRnaStore::new();
         let mut dna_1 = Dna2Rna::new(&mut rna_1);
-        dna_1.set_dna_and_prefix("CIIC", None);
+        dna_1.set_dna_and_prefix("CIIC", None).unwrap();
         let pt_1 = dna_1.pattern();
-        assert_eq!(pt_1, Some(vec![PItem::Base('I')]));
+        assert_eq!(pt_1, Ok(Some(vec![PItem::Base(Base::I)])));
         let mut rna_2 = VecRnaStore::new();
         let mut dna_2 = Dna2Rna::new(&mut rna_2);
-        dna_2.set_dna_and_prefix("IIPIPICPIICICIIF", None);
+        dna_2.set_dna_and_prefix("IIPIPICPIICICIIF", None).unwrap();
         let pt_2 = dna_2.pattern();
         assert_eq!(
             pt_2,
-            Some(vec![
+            Ok(Some(vec![
                 PItem::Open,
                 PItem::Skip(2),
                 PItem::Close,
-                PItem::Base('P')
-            ])
+                PItem::Base(Base::P)
+            ]))
         );
         let mut rna_3 = VecRnaStore::new();
         let mut dna_3 = Dna2Rna::new(&mut rna_3);
-        dna_3.set_dna_and_prefix("IIIICFPICFCIIC", None);
+        dna_3.set_dna_and_prefix("IIIICFPICFCIIC", None).unwrap();
         let pt_3 = dna_3.pattern();
-        assert_eq!(rna_3.rna, vec!["ICFPICF".to_string()]);
-        assert_eq!(pt_3, Some(vec![PItem::Base('I')]));
+        assert_eq!(rna_3.rna, vec!["ICFPICF".parse().unwrap()]);
+        assert_eq!(pt_3, Ok(Some(vec![PItem::Base(Base::I)])));
     }
 
     #[test]
     fn nat() {
         init();
-        assert_eq!(Dna2Rna::nat(Rope::from_str("P").chars()), Some((0, 1)));
-        assert_eq!(Dna2Rna::nat(Rope::from_str("IP").chars()), Some((0, 2)));
-        assert_eq!(Dna2Rna::nat(Rope::from_str("FP").chars()), Some((0, 2)));
-        assert_eq!(Dna2Rna::nat(Rope::from_str("CP").chars()), Some((1, 2)));
-        assert_eq!(Dna2Rna::nat(Rope::from_str("CIP").chars()), Some((1, 3)));
-        assert_eq!(Dna2Rna::nat(Rope::from_str("CFP").chars()), Some((1, 3)));
-        assert_eq!(Dna2Rna::nat(Rope::from_str("ICP").chars()), Some((2, 3)));
-        assert_eq!(Dna2Rna::nat(Rope::from_str("ICP").chars()), Some((2, 3)));
-        assert_eq!(
-            Dna2Rna::nat(Rope::from_str("IFCICFICFP").chars()),
-            Some((148, 10))
-        );
+        assert_eq!(Dna2Rna::nat(dna("P").iter()), Some((0, 1)));
+        assert_eq!(Dna2Rna::nat(dna("IP").iter()), Some((0, 2)));
+        assert_eq!(Dna2Rna::nat(dna("FP").iter()), Some((0, 2)));
+        assert_eq!(Dna2Rna::nat(dna("CP").iter()), Some((1, 2)));
+        assert_eq!(Dna2Rna::nat(dna("CIP").iter()), Some((1, 3)));
+        assert_eq!(Dna2Rna::nat(dna("CFP").iter()), Some((1, 3)));
+        assert_eq!(Dna2Rna::nat(dna("ICP").iter()), Some((2, 3)));
+        assert_eq!(Dna2Rna::nat(dna("ICP").iter()), Some((2, 3)));
+        assert_eq!(Dna2Rna::nat(dna("IFCICFICFP").iter()), Some((148, 10)));
+    }
+
+    #[test]
+    fn huge_nat() {
+        init();
+        let wide = "I".repeat(120) + "CP";
+        assert_eq!(Dna2Rna::nat(dna(&wide).iter()), Some((usize::MAX, 122)));
+        let ones = "C".repeat(64) + "P";
+        assert_eq!(Dna2Rna::nat(dna(&ones).iter()), Some((usize::MAX, 65)));
+        let top = "I".repeat(63) + "CP";
+        assert_eq!(Dna2Rna::nat(dna(&top).iter()), Some((1 << 63, 65)));
+        // a huge skip consumes the whole number and then fails to match
+        let mut rna_1 = VecRnaStore::new();
+        let mut dna_1 = Dna2Rna::new(&mut rna_1);
+        let skip = format!("IP{}IICIICCFP", wide);
+        dna_1.set_dna_and_prefix(&skip, None).unwrap();
+        assert_eq!(dna_1.execute_step(), Ok(StepOutcome::PatternMismatch));
+        assert_eq!(dna_1.dna.to_string(), "CFP");
+        // a reference to a huge environment index is empty
+        let mut rna_2 = VecRnaStore::new();
+        let mut dna_2 = Dna2Rna::new(&mut rna_2);
+        let reference = format!("IICIPP{}IICCFP", wide);
+        dna_2.set_dna_and_prefix(&reference, None).unwrap();
+        assert_eq!(dna_2.execute_step(), Ok(StepOutcome::Matched));
+        assert_eq!(dna_2.dna.to_string(), "CFP");
+        // a huge protection level leaves an empty environment empty
+        let mut rna_3 = VecRnaStore::new();
+        let mut dna_3 = Dna2Rna::new(&mut rna_3);
+        let empty = format!("IIPIICIICIF{}PIICCFP", wide);
+        dna_3.set_dna_and_prefix(&empty, None).unwrap();
+        assert_eq!(dna_3.execute_step(), Ok(StepOutcome::Matched));
+        assert_eq!(dna_3.dna.to_string(), "CFP");
+        // but can't quote anything else
+        let mut rna_4 = VecRnaStore::new();
+        let mut dna_4 = Dna2Rna::new(&mut rna_4);
+        let full = format!("IIPIPCPIICIICIF{}PIICC", wide);
+        dna_4.set_dna_and_prefix(&full, None).unwrap();
+        assert_eq!(dna_4.execute_step(), Err(Dna2RnaError::DnaTooLong));
+    }
+
+    #[test]
+    fn too_long() {
+        init();
+        let mut rna = VecRnaStore::new();
+        let mut d = Dna2Rna::new(&mut rna);
+        // quoting one base 300 times is far more bases than can be counted
+        let mut program = asm::assemble("( !1 ) / \\0_300").unwrap();
+        program.append(dna("C"));
+        d.dna = program.clone();
+        assert_eq!(d.execute_step(), Err(Dna2RnaError::DnaTooLong));
+        assert_eq!(d.dna, program);
     }
 
     #[test]
     fn consts() {
         init();
+        assert_eq!(Dna2Rna::consts(dna("IP").iter()), (bases(""), 0));
+        assert_eq!(Dna2Rna::consts(dna("IF").iter()), (bases(""), 0));
+        assert_eq!(Dna2Rna::consts(dna("CFIF").iter()), (bases("IC"), 2));
         assert_eq!(
-            Dna2Rna::consts(Rope::from_str("IP").chars()),
-            (String::from(""), 0)
-        );
-        assert_eq!(
-            Dna2Rna::consts(Rope::from_str("IF").chars()),
-            (String::from(""), 0)
-        );
-        assert_eq!(
-            Dna2Rna::consts(Rope::from_str("CFIF").chars()),
-            (String::from("IC"), 2)
-        );
-        assert_eq!(
-            Dna2Rna::consts(Rope::from_str("ICFPICFP").chars()),
-            (String::from("PCFPCF"), 8)
+            Dna2Rna::consts(dna("ICFPICFP").iter()),
+            (bases("PCFPCF"), 8)
         );
     }
 
@@ -522,7 +1142,7 @@ mod tests {
         init();
         for i in 0..100 {
             log::info!("i: {}", i);
-            let r = Dna2Rna::nat(Dna2Rna::asnat(i).chars());
+            let r = Dna2Rna::nat(Dna2Rna::asnat(i).iter());
             assert_ne!(r, None);
             if let Some((n, _)) = r {
                 assert_eq!(n, i);
@@ -533,14 +1153,17 @@ mod tests {
     #[test]
     fn quote() {
         init();
-        assert_eq!(Dna2Rna::quote(&Rope::from_str("ICFP")).to_string(), "CFPIC")
+        assert_eq!(
+            Dna2Rna::protect(1, &dna("ICFP")).unwrap().to_string(),
+            "CFPIC"
+        )
     }
 
     #[test]
     fn protect() {
         init();
         assert_eq!(
-            Dna2Rna::protect(3, &Rope::from_str("ICFP")).to_string(),
+            Dna2Rna::protect(3, &dna("ICFP")).unwrap().to_string(),
             "PICCFFP"
         )
     }
@@ -550,15 +1173,22 @@ mod tests {
         init();
         let mut rna = VecRnaStore::new();
         let mut dna = Dna2Rna::new(&mut rna);
-        dna.set_dna_and_prefix("IIIIIIIIIIICFPFF", None);
-        dna.match_replace(
+        dna.set_dna_and_prefix("IIIIIIIIIIICFPFF", None).unwrap();
+        find_and_replace(
+            &mut dna,
             &[
-                PItem::Base('I'),
-                PItem::Base('I'),
-                PItem::Search("ICFP".into()),
-                PItem::Base('F'),
+                PItem::Base(Base::I),
+                PItem::Base(Base::I),
+                PItem::Search(bases("ICFP")),
+                PItem::Base(Base::F),
             ],
-            &[TItem::Base('C'), TItem::Base('P')],
+            &[TItem::Base(Base::C), TItem::Base(Base::P)],
+        )
+        .unwrap();
+        assert_eq!(dna.dna.to_string(), "CPF");
+        assert_eq!(
+            find_and_replace(&mut dna, &[PItem::Base(Base::I)], &[TItem::Base(Base::C)]),
+            Ok(false)
         );
         assert_eq!(dna.dna.to_string(), "CPF");
     }
@@ -568,18 +1198,445 @@ mod tests {
         init();
         let mut rna_1 = VecRnaStore::new();
         let mut dna_1 = Dna2Rna::new(&mut rna_1);
-        dna_1.set_dna_and_prefix("IIPIPICPIICICIIFICCIFPPIICCFPC", None);
-        dna_1.execute_step();
+        dna_1
+            .set_dna_and_prefix("IIPIPICPIICICIIFICCIFPPIICCFPC", None)
+            .unwrap();
+        assert_eq!(dna_1.execute_step(), Ok(StepOutcome::Matched));
         assert_eq!(dna_1.dna.to_string(), "PICFC");
         let mut rna_2 = VecRnaStore::new();
         let mut dna_2 = Dna2Rna::new(&mut rna_2);
-        dna_2.set_dna_and_prefix("IIPIPICPIICICIIFICCIFCCCPPIICCFPC", None);
-        dna_2.execute_step();
+        dna_2
+            .set_dna_and_prefix("IIPIPICPIICICIIFICCIFCCCPPIICCFPC", None)
+            .unwrap();
+        assert_eq!(dna_2.execute_step(), Ok(StepOutcome::Matched));
         assert_eq!(dna_2.dna.to_string(), "PIICCFCFFPC");
         let mut rna_3 = VecRnaStore::new();
         let mut dna_3 = Dna2Rna::new(&mut rna_3);
-        dna_3.set_dna_and_prefix("IIPIPIICPIICIICCIICFCFC", None);
-        dna_3.execute_step();
+        dna_3
+            .set_dna_and_prefix("IIPIPIICPIICIICCIICFCFC", None)
+            .unwrap();
+        assert_eq!(dna_3.execute_step(), Ok(StepOutcome::Matched));
         assert_eq!(dna_3.dna.to_string(), "I");
     }
+
+    #[test]
+    fn trailing_newline() {
+        init();
+        let mut rna = VecRnaStore::new();
+        let mut d = Dna2Rna::new(&mut rna);
+        d.set_track_origins(true);
+        d.set_dna_and_prefix("CIIC\n", Some("IIPIPICPIICICIIFICCIFPPIICCFPC\n"))
+            .unwrap();
+        assert_eq!(d.dna.to_string(), "IIPIPICPIICICIIFICCIFPPIICCFPCCIIC");
+        assert_eq!(d.origin(30).map(|o| o.offset), Some(30));
+        assert_eq!(d.execute_step(), Ok(StepOutcome::Matched));
+    }
+
+    #[test]
+    fn errors() {
+        init();
+        let mut rna_1 = VecRnaStore::new();
+        let mut dna_1 = Dna2Rna::new(&mut rna_1);
+        assert_eq!(
+            dna_1.set_dna_and_prefix("CFPICIIC-", None),
+            Err(Dna2RnaError::InvalidBase {
+                offset: 8,
+                found: '-'
+            })
+        );
+        let mut rna_2 = VecRnaStore::new();
+        let mut dna_2 = Dna2Rna::new(&mut rna_2);
+        assert_eq!(
+            dna_2.set_dna_and_prefix("IIPIPICxP", Some("IIC")),
+            Err(Dna2RnaError::InvalidBase {
+                offset: 10,
+                found: 'x'
+            })
+        );
+        let mut rna_3 = VecRnaStore::new();
+        let mut dna_3 = Dna2Rna::new(&mut rna_3);
+        dna_3.set_dna_and_prefix("CIICIIICFP", None).unwrap();
+        assert_eq!(
+            dna_3.execute_step(),
+            Err(Dna2RnaError::PrematureEnd {
+                offset: 4,
+                source: None
+            })
+        );
+        assert_eq!(dna_3.dna.to_string(), "CIICIIICFP");
+        let mut rna_4 = VecRnaStore::new();
+        let mut dna_4 = Dna2Rna::new(&mut rna_4);
+        dna_4.set_dna_and_prefix("ICFP", None).unwrap();
+        assert_eq!(
+            find_and_replace(&mut dna_4, &[PItem::Open, PItem::Close, PItem::Close], &[]),
+            Err(Dna2RnaError::UnbalancedClose { item: 2 })
+        );
+        let mut rna_5 = VecRnaStore::new();
+        let mut dna_5 = Dna2Rna::new(&mut rna_5);
+        dna_5.set_dna_and_prefix("CIIC", None).unwrap();
+        assert_eq!(dna_5.execute_step(), Ok(StepOutcome::TruncatedTemplate));
+    }
+
+    #[test]
+    fn error_sources() {
+        init();
+        // the first step takes the first eight bases, the second runs out
+        // inside an rna command one base into what's left
+        let input = "CIICIICICIIICFP";
+        let mut rna = VecRnaStore::new();
+        let mut d = Dna2Rna::new(&mut rna);
+        d.set_track_origins(true);
+        d.set_dna_and_prefix(input, None).unwrap();
+        assert_eq!(d.execute_step(), Ok(StepOutcome::Matched));
+        let snapshot = d.snapshot();
+        let err = Dna2RnaError::PrematureEnd {
+            offset: 1,
+            source: Some(9),
+        };
+        assert_eq!(d.execute_step(), Err(err));
+        assert_eq!(
+            err.to_string(),
+            "dna ended inside rna command at offset 1 (input offset 9)"
+        );
+        assert_eq!(d.snapshot(), snapshot);
+    }
+
+    #[test]
+    fn outcomes() {
+        init();
+        let mut rna_1 = VecRnaStore::new();
+        let mut dna_1 = Dna2Rna::new(&mut rna_1);
+        // matches "I", then fails to match "C", then runs out mid pattern
+        dna_1.set_dna_and_prefix("CIICIICIFIICIICIP", None).unwrap();
+        assert_eq!(dna_1.execute_step(), Ok(StepOutcome::Matched));
+        assert_eq!(dna_1.execute_step(), Ok(StepOutcome::PatternMismatch));
+        assert_eq!(
+            dna_1.execute(),
+            Ok(RunOutcome::Done(StepOutcome::TruncatedPattern))
+        );
+        assert_eq!(
+            *dna_1.stats(),
+            StepStats {
+                matched: 1,
+                mismatched: 1,
+                finished: 0,
+                truncated_pattern: 1,
+                truncated_template: 0,
+            }
+        );
+        assert_eq!(dna_1.stats().steps(), 3);
+        let mut rna_2 = VecRnaStore::new();
+        let mut dna_2 = Dna2Rna::new(&mut rna_2);
+        assert_eq!(dna_2.execute(), Ok(RunOutcome::Done(StepOutcome::Finished)));
+        assert_eq!(dna_2.stats().finished, 1);
+    }
+
+    struct VecTracer {
+        steps: Vec<TraceStep>,
+    }
+
+    impl Tracer for VecTracer {
+        fn trace(&mut self, step: &TraceStep) {
+            self.steps.push(step.clone());
+        }
+    }
+
+    #[test]
+    fn trace() {
+        init();
+        let mut rna = VecRnaStore::new();
+        let mut tracer = VecTracer { steps: vec![] };
+        let mut dna = Dna2Rna::new(&mut rna);
+        dna.set_tracer(&mut tracer);
+        dna.set_dna_and_prefix("IIPIPICPIICICIIFICCIFPPIICCFPCIIC", None)
+            .unwrap();
+        assert_eq!(
+            dna.execute(),
+            Ok(RunOutcome::Done(StepOutcome::TruncatedTemplate))
+        );
+        assert_eq!(
+            tracer.steps,
+            vec![
+                TraceStep {
+                    step: 0,
+                    outcome: StepOutcome::Matched,
+                    pattern: vec![
+                        PItem::Open,
+                        PItem::Skip(2),
+                        PItem::Close,
+                        PItem::Base(Base::P)
+                    ],
+                    template: vec![TItem::Base(Base::P), TItem::Base(Base::I), TItem::Ref(0, 0)],
+                    consumed: 26,
+                    matched: Some(3),
+                    envs: vec![2],
+                    dna_len: 8,
+                },
+                TraceStep {
+                    step: 1,
+                    outcome: StepOutcome::TruncatedTemplate,
+                    pattern: vec![
+                        PItem::Base(Base::F),
+                        PItem::Base(Base::P),
+                        PItem::Base(Base::C),
+                        PItem::Base(Base::I),
+                    ],
+                    template: vec![],
+                    consumed: 8,
+                    matched: None,
+                    envs: vec![],
+                    dna_len: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn snapshot() {
+        init();
+        let mut rna = VecRnaStore::new();
+        let mut dna = Dna2Rna::new(&mut rna);
+        // two steps with an empty pattern and template
+        dna.set_dna_and_prefix("IICIICIICIICII", None).unwrap();
+        assert_eq!(dna.execute_step(), Ok(StepOutcome::Matched));
+        let snapshot = dna.snapshot();
+        assert_eq!(snapshot.stats.steps(), 1);
+        for _ in 0..2 {
+            dna.restore(&snapshot);
+            assert_eq!(dna.execute_step(), Ok(StepOutcome::Matched));
+            assert_eq!(dna.dna.to_string(), "II");
+            assert_eq!(dna.stats().steps(), 2);
+        }
+        dna.restore(&snapshot);
+        assert_eq!(dna.snapshot(), snapshot);
+    }
+
+    #[test]
+    fn limits() {
+        init();
+        let mut rna = VecRnaStore::new();
+        let mut dna = Dna2Rna::new(&mut rna);
+        // an instruction that matches a copy of itself and puts back three,
+        // so it runs forever and the DNA grows every step
+        let copier = (1..100)
+            .map(|n| asm::assemble(&format!("( !{} ) / \\0 \\0 \\0", n)).unwrap())
+            .enumerate()
+            .find(|(i, d)| d.len() == i + 1)
+            .unwrap()
+            .1;
+        let mut program = copier.clone();
+        program.append(copier);
+        dna.dna = program;
+        dna.set_limits(Limits {
+            max_steps: Some(3),
+            ..Limits::default()
+        });
+        assert_eq!(dna.execute(), Ok(RunOutcome::Limited(Limit::Steps)));
+        assert_eq!(dna.stats().steps(), 3);
+        dna.set_limits(Limits {
+            max_dna_len: Some(1000),
+            ..Limits::default()
+        });
+        assert_eq!(dna.execute(), Ok(RunOutcome::Limited(Limit::DnaLength)));
+        assert!(dna.dna.len() > 1000);
+        let cancel = Arc::new(AtomicBool::new(false));
+        dna.set_limits(Limits {
+            cancel: Some(cancel.clone()),
+            deadline: Some(Instant::now()),
+            ..Limits::default()
+        });
+        assert_eq!(dna.execute(), Ok(RunOutcome::Limited(Limit::Deadline)));
+        cancel.store(true, Ordering::Relaxed);
+        assert_eq!(dna.execute(), Ok(RunOutcome::Limited(Limit::Cancelled)));
+    }
+
+    #[derive(Default)]
+    struct EventLog {
+        events: Vec<String>,
+    }
+
+    impl Dna2RnaObserver for EventLog {
+        fn pattern_decoded(&mut self, pattern: &[PItem]) {
+            self.events.push(format!("pattern {:?}", pattern));
+        }
+
+        fn template_decoded(&mut self, template: &[TItem]) {
+            self.events.push(format!("template {:?}", template));
+        }
+
+        fn matched(&mut self, len: usize) {
+            self.events.push(format!("matched {}", len));
+        }
+
+        fn mismatched(&mut self) {
+            self.events.push("mismatched".to_string());
+        }
+
+        fn env_captured(&mut self, n: usize, env: &Dna) {
+            self.events.push(format!("env {} {}", n, env));
+        }
+
+        fn dna_len_changed(&mut self, old: usize, new: usize) {
+            self.events.push(format!("dna {} -> {}", old, new));
+        }
+
+        fn rna_emitted(&mut self, rna: RnaCommand, origin: Option<Origin>) {
+            self.events.push(format!("rna {} from {:?}", rna, origin));
+        }
+    }
+
+    struct StepCounter(usize);
+
+    impl Dna2RnaObserver for StepCounter {
+        fn template_decoded(&mut self, _template: &[TItem]) {
+            self.0 += 1;
+        }
+    }
+
+    #[test]
+    fn observers() {
+        init();
+        let mut rna = VecRnaStore::new();
+        let mut log = EventLog::default();
+        let mut counter = StepCounter(0);
+        let mut dna = Dna2Rna::new(&mut rna);
+        dna.add_observer(&mut log);
+        dna.add_observer(&mut counter);
+        dna.dna = asm::assemble("( !1 ) / @PIPIIIC \\0\n!100 /\n.dna CFP").unwrap();
+        assert_eq!(dna.execute_step(), Ok(StepOutcome::Matched));
+        assert_eq!(dna.execute_step(), Ok(StepOutcome::PatternMismatch));
+        assert_eq!(
+            log.events,
+            vec![
+                "pattern [Open, Skip(1), Close]",
+                "rna PIPIIIC from None",
+                "template [Ref(0, 0)]",
+                "matched 1",
+                "env 0 I",
+                "dna 49 -> 19",
+                "pattern [Skip(100)]",
+                "template []",
+                "mismatched",
+                "dna 19 -> 3",
+            ]
+        );
+        assert_eq!(counter.0, 2);
+    }
+
+    #[test]
+    fn origins() {
+        init();
+        let gene = asm::assemble("!1500 ( !10 ) / \\0_2 \\0")
+            .unwrap()
+            .to_string();
+        let input = gene.clone() + &"C".repeat(1500) + "IIIPIPIIIC" + &"F".repeat(100);
+        let cmd = gene.len() + 1500;
+        let mut rna = VecRnaStore::new();
+        let mut log = EventLog::default();
+        let mut dna = Dna2Rna::new(&mut rna);
+        dna.add_observer(&mut log);
+        dna.set_track_origins(true);
+        dna.set_dna_and_prefix(&input, None).unwrap();
+        assert_eq!(
+            dna.origin(gene.len() + 7),
+            Some(Origin {
+                offset: gene.len() + 7,
+                quotes: 0
+            })
+        );
+        assert_eq!(dna.execute_step(), Ok(StepOutcome::Matched));
+        // the command quoted twice, then as it was
+        let quoted = Dna2Rna::protect(2, &"IIIPIPIIIC".parse().unwrap())
+            .unwrap()
+            .len();
+        assert_eq!(
+            dna.origin(0),
+            Some(Origin {
+                offset: cmd,
+                quotes: 2
+            })
+        );
+        assert_eq!(dna.source_offset(0), None);
+        assert_eq!(dna.source_offset(quoted + 3), Some(cmd + 3));
+        assert_eq!(
+            dna.origin(quoted + 10),
+            Some(Origin {
+                offset: cmd + 10,
+                quotes: 0
+            })
+        );
+        assert_eq!(dna.execute_step(), Ok(StepOutcome::TruncatedPattern));
+        drop(dna);
+        let expected = format!(
+            "rna PIPIIIC from {:?}",
+            Some(Origin {
+                offset: cmd + 3,
+                quotes: 0
+            })
+        );
+        assert!(log.events.contains(&expected));
+        assert_eq!(rna.rna, vec![RnaCommand::AddBlack]);
+    }
+
+    #[test]
+    fn short_origins() {
+        init();
+        // a few bases of a short input spliced in between template bases
+        let gene = asm::assemble("( !4 ) / ICF \\0 P").unwrap().to_string();
+        let input = gene.clone() + "CCCCFFFF";
+        let mut rna = VecRnaStore::new();
+        let mut dna = Dna2Rna::new(&mut rna);
+        dna.set_dna_and_prefix(&input, None).unwrap();
+        assert_eq!(dna.origin(gene.len()), None);
+        dna.set_track_origins(true);
+        dna.set_dna_and_prefix(&input, None).unwrap();
+        assert_eq!(dna.execute_step(), Ok(StepOutcome::Matched));
+        assert_eq!(dna.dna.to_string(), "ICFCCCCPFFFF");
+        assert_eq!(dna.origin(0), None);
+        assert_eq!(dna.source_offset(3), Some(gene.len()));
+        assert_eq!(dna.source_offset(6), Some(gene.len() + 3));
+        assert_eq!(dna.origin(7), None);
+        assert_eq!(dna.source_offset(8), Some(gene.len() + 4));
+    }
+
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn rna_stores() {
+        init();
+        let mut vec_store = VecRnaStore::new();
+        let mut tee = TeeRnaStore::new(&mut vec_store, WriterRnaStore::new(vec![]));
+        {
+            let mut dna = Dna2Rna::new(&mut tee);
+            dna.set_dna_and_prefix("IIIPIPIIICIIIPFFFFFFIIC", None)
+                .unwrap();
+            dna.execute_step().unwrap();
+        }
+        let written = tee.second.finish().unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), "PIPIIICPFFFFFF");
+        assert_eq!(
+            vec_store.rna,
+            vec![RnaCommand::AddBlack, "PFFFFFF".parse().unwrap()]
+        );
+        let mut none: Option<VecRnaStore> = None;
+        none.store(RnaCommand::AddBlack);
+        let mut failing = WriterRnaStore::new(FailingWriter);
+        for _ in 0..10000 {
+            failing.store(RnaCommand::AddBlack);
+        }
+        assert_eq!(
+            failing.finish().err().map(|e| e.to_string()),
+            Some("disk full".to_string())
+        );
+    }
 }
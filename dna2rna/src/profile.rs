@@ -0,0 +1,171 @@
+//! Attributes execution to where the patterns came from in the input.
+
+use crate::{Dna2RnaObserver, PItem, StepOutcome, TItem};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Totals for the steps whose pattern started at one input offset.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Site {
+    /// Offset in the prefix + DNA input, `None` for all the patterns that
+    /// don't have one, see `Dna2Rna::source_offset`. Only DNA loaded with
+    /// `Dna2Rna::set_track_origins` on has offsets.
+    pub source: Option<usize>,
+    pub steps: usize,
+    pub matched: usize,
+    pub time: Duration,
+    /// Bases taken up by patterns and templates.
+    pub consumed: usize,
+    /// The pattern and template the first time the site ran, written as in
+    /// `disasm`. Empty for the site without a source.
+    pub code: String,
+}
+
+impl fmt::Display for Site {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let source = match self.source {
+            Some(s) => s.to_string(),
+            None => "-".to_string(),
+        };
+        write!(
+            f,
+            "{:>9} {:>9} {:>9} {:>10.3} {:>10}  {}",
+            source,
+            self.steps,
+            self.matched,
+            self.time.as_secs_f64() * 1000.0,
+            self.consumed,
+            self.code
+        )
+    }
+}
+
+/// Observer that collects a `Site` per pattern source.
+#[derive(Default)]
+pub struct Profiler {
+    sites: HashMap<Option<usize>, Site>,
+    current: Option<(Option<usize>, Instant)>,
+    code: Option<String>,
+}
+
+pub const SITE_HEADER: &str = "   source     steps   matched    time ms   consumed  code";
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    /// The `n` sites that took the most time, hottest first.
+    pub fn hottest(&self, n: usize) -> Vec<&Site> {
+        let mut sites: Vec<&Site> = self.sites.values().collect();
+        sites.sort_by(|a, b| b.time.cmp(&a.time).then(b.steps.cmp(&a.steps)));
+        sites.truncate(n);
+        sites
+    }
+
+    fn is_new(&self) -> bool {
+        match self.current {
+            Some((source @ Some(_), _)) => !self.sites.contains_key(&source),
+            _ => false,
+        }
+    }
+}
+
+impl Dna2RnaObserver for Profiler {
+    fn step_started(&mut self, source: Option<usize>) {
+        self.current = Some((source, Instant::now()));
+    }
+
+    fn pattern_decoded(&mut self, pattern: &[PItem]) {
+        if self.is_new() {
+            let items: Vec<String> = pattern.iter().map(|p| p.to_string()).collect();
+            self.code = Some(items.join(" "));
+        }
+    }
+
+    fn template_decoded(&mut self, template: &[TItem]) {
+        if let Some(code) = self.code.as_mut() {
+            let items: Vec<String> = template.iter().map(|t| t.to_string()).collect();
+            code.push_str(" / ");
+            code.push_str(&items.join(" "));
+        }
+    }
+
+    fn step_finished(&mut self, outcome: StepOutcome, consumed: usize) {
+        let (source, started) = match self.current.take() {
+            Some(current) => current,
+            None => return,
+        };
+        let code = self.code.take();
+        let site = self.sites.entry(source).or_insert_with(|| Site {
+            source,
+            code: code.unwrap_or_default(),
+            ..Site::default()
+        });
+        site.steps += 1;
+        if outcome == StepOutcome::Matched {
+            site.matched += 1;
+        }
+        site.time += started.elapsed();
+        site.consumed += consumed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm, Dna2Rna, Limits, VecRnaStore};
+
+    #[test]
+    fn sites() {
+        // matches the copy of itself that follows and puts back three, so
+        // after the first step it's always a copy of the second one running
+        let copier = (1..100)
+            .map(|n| asm::assemble(&format!("( !{} ) / \\0 \\0 \\0", n)).unwrap())
+            .enumerate()
+            .find(|(i, d)| d.len() == i + 1)
+            .unwrap()
+            .1
+            .to_string();
+        let body = copier.clone() + &"F".repeat(2000);
+        let mut rna = VecRnaStore::new();
+        let mut profiler = Profiler::new();
+        let mut dna = Dna2Rna::new(&mut rna);
+        dna.add_observer(&mut profiler);
+        dna.set_track_origins(true);
+        dna.set_dna_and_prefix(&body, Some(&copier)).unwrap();
+        assert_eq!(dna.source_offset(0), Some(0));
+        assert_eq!(dna.source_offset(copier.len() + 5), Some(copier.len() + 5));
+        dna.set_limits(Limits {
+            max_steps: Some(5),
+            ..Limits::default()
+        });
+        dna.execute().unwrap();
+        drop(dna);
+        let mut sites = profiler.hottest(10);
+        sites.sort_by_key(|s| s.source);
+        let n = copier.len();
+        let code = format!("( !{} ) / \\0_0 \\0_0 \\0_0", n);
+        assert_eq!(sites.len(), 2);
+        assert_eq!(
+            (
+                sites[0].source,
+                sites[0].steps,
+                sites[0].consumed,
+                &sites[0].code
+            ),
+            (Some(0), 1, n, &code)
+        );
+        assert_eq!(
+            (
+                sites[1].source,
+                sites[1].steps,
+                sites[1].matched,
+                &sites[1].code
+            ),
+            (Some(n), 4, 4, &code)
+        );
+        assert_eq!(profiler.hottest(1).len(), 1);
+    }
+}
@@ -0,0 +1,186 @@
+//! RNA commands, the output of `Dna2Rna` and the input of `rna2fuun`.
+
+use crate::Base;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Number of bases in every RNA command.
+pub const RNA_LEN: usize = 7;
+
+/// A single RNA command, one of the 20 the spec gives a meaning to or any
+/// other seven bases.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum RnaCommand {
+    AddBlack,
+    AddRed,
+    AddGreen,
+    AddYellow,
+    AddBlue,
+    AddMagenta,
+    AddCyan,
+    AddWhite,
+    AddTransparent,
+    AddOpaque,
+    ClearBucket,
+    Move,
+    TurnCounterClockwise,
+    TurnClockwise,
+    Mark,
+    Line,
+    TryFill,
+    AddBitmap,
+    Compose,
+    Clip,
+    /// Bases that aren't a known command, which do nothing.
+    Unknown([Base; RNA_LEN]),
+}
+
+const KNOWN: [(RnaCommand, [Base; RNA_LEN]); 20] = {
+    use Base::{C, F, I, P};
+    [
+        (RnaCommand::AddBlack, [P, I, P, I, I, I, C]),
+        (RnaCommand::AddRed, [P, I, P, I, I, I, P]),
+        (RnaCommand::AddGreen, [P, I, P, I, I, C, C]),
+        (RnaCommand::AddYellow, [P, I, P, I, I, C, F]),
+        (RnaCommand::AddBlue, [P, I, P, I, I, C, P]),
+        (RnaCommand::AddMagenta, [P, I, P, I, I, F, C]),
+        (RnaCommand::AddCyan, [P, I, P, I, I, F, F]),
+        (RnaCommand::AddWhite, [P, I, P, I, I, P, C]),
+        (RnaCommand::AddTransparent, [P, I, P, I, I, P, F]),
+        (RnaCommand::AddOpaque, [P, I, P, I, I, P, P]),
+        (RnaCommand::ClearBucket, [P, I, I, P, I, C, P]),
+        (RnaCommand::Move, [P, I, I, I, I, I, P]),
+        (RnaCommand::TurnCounterClockwise, [P, C, C, C, C, C, P]),
+        (RnaCommand::TurnClockwise, [P, F, F, F, F, F, P]),
+        (RnaCommand::Mark, [P, C, C, I, F, F, P]),
+        (RnaCommand::Line, [P, F, F, I, C, C, P]),
+        (RnaCommand::TryFill, [P, I, I, P, I, I, P]),
+        (RnaCommand::AddBitmap, [P, C, C, P, F, F, P]),
+        (RnaCommand::Compose, [P, F, F, P, C, C, P]),
+        (RnaCommand::Clip, [P, F, F, I, C, C, F]),
+    ]
+};
+
+/// Errors parsing an `RnaCommand` from text.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ParseRnaError {
+    /// A character that isn't a base, `offset` counts characters.
+    InvalidBase { offset: usize, found: char },
+    /// Text with a number of bases other than seven.
+    WrongLength { len: usize },
+}
+
+impl fmt::Display for ParseRnaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseRnaError::InvalidBase { offset, found } => {
+                write!(f, "invalid base {:?} at offset {}", found, offset)
+            }
+            ParseRnaError::WrongLength { len } => {
+                write!(f, "rna command has {} bases instead of {}", len, RNA_LEN)
+            }
+        }
+    }
+}
+
+impl Error for ParseRnaError {}
+
+impl RnaCommand {
+    pub fn from_bases(bases: [Base; RNA_LEN]) -> RnaCommand {
+        match KNOWN.iter().find(|(_, code)| *code == bases) {
+            Some((command, _)) => *command,
+            None => RnaCommand::Unknown(bases),
+        }
+    }
+
+    pub fn bases(self) -> [Base; RNA_LEN] {
+        match self {
+            RnaCommand::Unknown(bases) => bases,
+            known => KNOWN.iter().find(|(c, _)| *c == known).unwrap().1,
+        }
+    }
+
+    /// Whether the command changes the pixels of a bitmap.
+    pub fn is_draw(self) -> bool {
+        matches!(
+            self,
+            RnaCommand::Line | RnaCommand::TryFill | RnaCommand::Compose | RnaCommand::Clip
+        )
+    }
+}
+
+impl FromStr for RnaCommand {
+    type Err = ParseRnaError;
+
+    fn from_str(s: &str) -> Result<RnaCommand, ParseRnaError> {
+        let mut bases = [Base::I; RNA_LEN];
+        let mut len = 0;
+        for (offset, c) in s.chars().enumerate() {
+            let b = Base::from_char(c).ok_or(ParseRnaError::InvalidBase { offset, found: c })?;
+            if len < RNA_LEN {
+                bases[len] = b;
+            }
+            len += 1;
+        }
+        if len != RNA_LEN {
+            return Err(ParseRnaError::WrongLength { len });
+        }
+        Ok(RnaCommand::from_bases(bases))
+    }
+}
+
+impl fmt::Display for RnaCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s: String = self.bases().iter().map(|b| b.to_char()).collect();
+        f.write_str(&s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_encode() {
+        for (command, code) in KNOWN.iter() {
+            let text: String = code.iter().map(|b| b.to_char()).collect();
+            assert_eq!(text.parse::<RnaCommand>(), Ok(*command));
+            assert_eq!(command.to_string(), text);
+            assert_eq!(command.bases(), *code);
+        }
+        assert_eq!("PIPIIIC".parse(), Ok(RnaCommand::AddBlack));
+        assert_eq!(RnaCommand::Clip.to_string(), "PFFICCF");
+        let unknown: RnaCommand = "ICFPICF".parse().unwrap();
+        assert_eq!(
+            unknown,
+            RnaCommand::Unknown([
+                Base::I,
+                Base::C,
+                Base::F,
+                Base::P,
+                Base::I,
+                Base::C,
+                Base::F
+            ])
+        );
+        assert_eq!(unknown.to_string(), "ICFPICF");
+        assert!(RnaCommand::Clip.is_draw());
+        assert!(!RnaCommand::Mark.is_draw());
+        assert_eq!(
+            "PIPIIC".parse::<RnaCommand>(),
+            Err(ParseRnaError::WrongLength { len: 6 })
+        );
+        assert_eq!(
+            "PIPIIICP".parse::<RnaCommand>(),
+            Err(ParseRnaError::WrongLength { len: 8 })
+        );
+        assert_eq!(
+            "PIPXIIC".parse::<RnaCommand>(),
+            Err(ParseRnaError::InvalidBase {
+                offset: 3,
+                found: 'X'
+            })
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use crate::Base;
+
+/// Knuth-Morris-Pratt matcher for a fixed needle.
+///
+/// The haystack is consumed as a stream of bases, so searching a `Dna`
+/// walks each leaf once and never backs up, whatever the needle looks like.
+pub(crate) struct Kmp<'a> {
+    needle: &'a [Base],
+    fail: Vec<usize>,
+}
+
+impl<'a> Kmp<'a> {
+    pub(crate) fn new(needle: &'a [Base]) -> Kmp<'a> {
+        // fail[i] is the length of the longest proper border of needle[..=i]
+        let mut fail = vec![0; needle.len()];
+        let mut k = 0;
+        for i in 1..needle.len() {
+            while k > 0 && needle[i] != needle[k] {
+                k = fail[k - 1];
+            }
+            if needle[i] == needle[k] {
+                k += 1;
+            }
+            fail[i] = k;
+        }
+        Kmp { needle, fail }
+    }
+
+    /// Returns the number of bases read from `hay` up to and including the
+    /// end of the first occurrence of the needle.
+    pub(crate) fn find_end<I: Iterator<Item = Base>>(&self, hay: I) -> Option<usize> {
+        if self.needle.is_empty() {
+            return Some(0);
+        }
+        let mut k = 0;
+        for (i, b) in hay.enumerate() {
+            while k > 0 && b != self.needle[k] {
+                k = self.fail[k - 1];
+            }
+            if b == self.needle[k] {
+                k += 1;
+                if k == self.needle.len() {
+                    return Some(i + 1);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Dna;
+
+    fn bases(s: &str) -> Vec<Base> {
+        s.parse::<Dna>().unwrap().iter().collect()
+    }
+
+    #[test]
+    fn kmp() {
+        let needle = bases("IICIIF");
+        let kmp = Kmp::new(&needle);
+        assert_eq!(kmp.fail, vec![0, 1, 0, 1, 2, 0]);
+        assert_eq!(kmp.find_end(bases("IIIICIIICIIF").into_iter()), Some(12));
+        assert_eq!(kmp.find_end(bases("IIIICIIICIIC").into_iter()), None);
+        assert_eq!(Kmp::new(&[]).find_end(bases("CF").into_iter()), Some(0));
+        assert_eq!(
+            Kmp::new(&bases("P")).find_end(bases("CFP").into_iter()),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn find() {
+        let s: String = (0..5000)
+            .map(|i| Base::from_bits((i * i / 7) as u8).to_char())
+            .collect();
+        let d: Dna = s.parse().unwrap();
+        for (from, len) in &[(0, 1), (10, 5), (1000, 12), (3000, 30), (4990, 10)] {
+            let needle = &s[4990 - len..4990];
+            let expected = s[*from..].find(needle).map(|p| p + from);
+            assert_eq!(d.find(&bases(needle), *from), expected);
+        }
+        assert_eq!(d.find(&bases("I"), 5000), None);
+        assert_eq!(d.find(&[], 5000), Some(5000));
+    }
+}
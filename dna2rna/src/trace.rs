@@ -0,0 +1,343 @@
+//! Per-step execution traces.
+//!
+//! A `Tracer` set with `Dna2Rna::set_tracer` is handed a `TraceStep` after
+//! every successful step. Two writers are provided:
+//!
+//! * `JsonLinesTracer` writes one JSON object per step, with pattern and
+//!   template items written as in `disasm`.
+//! * `BinaryTracer` writes the `DNATRACE` magic followed by one record per
+//!   step. Numbers are LEB128 varints, the outcome is a byte in the order
+//!   of `StepOutcome`, and `matched` is stored plus one so that zero means
+//!   no match. A record is `step outcome consumed matched dna_len`, the
+//!   environment count and lengths, then the pattern and the template, each
+//!   as an item count followed by the items. An item starts with a tag:
+//!   `0..=3` is the base with that code, for patterns `4` is a skip with its
+//!   count, `5` a search with its length and bases packed four to a byte,
+//!   `6` an open and `7` a close, and for templates `4` is a reference with
+//!   its index and level and `5` a length reference with its index.
+
+use crate::{Base, PItem, StepOutcome, TItem};
+use std::io::{self, Write};
+
+/// What a single step decoded and did.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TraceStep {
+    /// Index of the step since the DNA was last set.
+    pub step: usize,
+    pub outcome: StepOutcome,
+    /// The pattern, empty if the DNA ran out while decoding it.
+    pub pattern: Vec<PItem>,
+    /// The template, empty unless the whole pattern was decoded.
+    pub template: Vec<TItem>,
+    /// Bases consumed decoding the pattern and the template.
+    pub consumed: usize,
+    /// Bases matched by the pattern, if it matched.
+    pub matched: Option<usize>,
+    /// Lengths of the environments bound by the match.
+    pub envs: Vec<usize>,
+    /// Length of the DNA after the step.
+    pub dna_len: usize,
+}
+
+impl TraceStep {
+    pub(crate) fn new(step: usize) -> TraceStep {
+        TraceStep {
+            step,
+            outcome: StepOutcome::Finished,
+            pattern: vec![],
+            template: vec![],
+            consumed: 0,
+            matched: None,
+            envs: vec![],
+            dna_len: 0,
+        }
+    }
+}
+
+pub trait Tracer {
+    fn trace(&mut self, step: &TraceStep);
+}
+
+fn outcome_code(outcome: StepOutcome) -> u8 {
+    match outcome {
+        StepOutcome::Matched => 0,
+        StepOutcome::PatternMismatch => 1,
+        StepOutcome::Finished => 2,
+        StepOutcome::TruncatedPattern => 3,
+        StepOutcome::TruncatedTemplate => 4,
+    }
+}
+
+/// Appends `n` as an LEB128 varint.
+pub(crate) fn push_varint(buf: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        buf.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+/// Appends `bases` packed four to a byte, first base in the low bits.
+pub(crate) fn push_bases<I: Iterator<Item = Base>>(buf: &mut Vec<u8>, bases: I) {
+    let mut byte = 0;
+    let mut n = 0;
+    for b in bases {
+        byte |= (b as u8) << (2 * n);
+        n += 1;
+        if n == 4 {
+            buf.push(byte);
+            byte = 0;
+            n = 0;
+        }
+    }
+    if n > 0 {
+        buf.push(byte);
+    }
+}
+
+/// Writes each step as a line of JSON.
+///
+/// Write errors stop the tracing, the first one is returned by `finish`.
+pub struct JsonLinesTracer<W: Write> {
+    out: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> JsonLinesTracer<W> {
+    pub fn new(out: W) -> JsonLinesTracer<W> {
+        JsonLinesTracer { out, error: None }
+    }
+
+    /// Flushes the output and returns it, or the first write error.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn write_step(&mut self, step: &TraceStep) -> io::Result<()> {
+        let items = |v: Vec<String>| -> String {
+            let quoted: Vec<String> = v
+                .iter()
+                .map(|s| format!("\"{}\"", s.replace('\\', "\\\\")))
+                .collect();
+            quoted.join(",")
+        };
+        let envs: Vec<String> = step.envs.iter().map(|n| n.to_string()).collect();
+        let matched = match step.matched {
+            Some(n) => n.to_string(),
+            None => "null".to_string(),
+        };
+        writeln!(
+            self.out,
+            "{{\"step\":{},\"outcome\":\"{:?}\",\"pattern\":[{}],\"template\":[{}],\
+             \"consumed\":{},\"matched\":{},\"envs\":[{}],\"dna_len\":{}}}",
+            step.step,
+            step.outcome,
+            items(step.pattern.iter().map(|p| p.to_string()).collect()),
+            items(step.template.iter().map(|t| t.to_string()).collect()),
+            step.consumed,
+            matched,
+            envs.join(","),
+            step.dna_len
+        )
+    }
+}
+
+impl<W: Write> Tracer for JsonLinesTracer<W> {
+    fn trace(&mut self, step: &TraceStep) {
+        if self.error.is_none() {
+            self.error = self.write_step(step).err();
+        }
+    }
+}
+
+/// Writes each step as a compact binary record, see the module docs.
+///
+/// Write errors stop the tracing, the first one is returned by `finish`.
+pub struct BinaryTracer<W: Write> {
+    out: W,
+    buf: Vec<u8>,
+    started: bool,
+    error: Option<io::Error>,
+}
+
+pub const BINARY_TRACE_MAGIC: &[u8] = b"DNATRACE";
+
+impl<W: Write> BinaryTracer<W> {
+    pub fn new(out: W) -> BinaryTracer<W> {
+        BinaryTracer {
+            out,
+            buf: vec![],
+            started: false,
+            error: None,
+        }
+    }
+
+    /// Flushes the output and returns it, or the first write error.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        if !self.started {
+            self.out.write_all(BINARY_TRACE_MAGIC)?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn varint(&mut self, n: usize) {
+        push_varint(&mut self.buf, n);
+    }
+
+    fn encode(&mut self, step: &TraceStep) {
+        self.varint(step.step);
+        self.buf.push(outcome_code(step.outcome));
+        self.varint(step.consumed);
+        self.varint(step.matched.map_or(0, |n| n + 1));
+        self.varint(step.dna_len);
+        self.varint(step.envs.len());
+        for n in &step.envs {
+            self.varint(*n);
+        }
+        self.varint(step.pattern.len());
+        for p in &step.pattern {
+            match p {
+                PItem::Base(b) => self.buf.push(*b as u8),
+                PItem::Skip(n) => {
+                    self.buf.push(4);
+                    self.varint(*n);
+                }
+                PItem::Search(s) => {
+                    self.buf.push(5);
+                    self.varint(s.len());
+                    push_bases(&mut self.buf, s.iter().cloned());
+                }
+                PItem::Open => self.buf.push(6),
+                PItem::Close => self.buf.push(7),
+            }
+        }
+        self.varint(step.template.len());
+        for t in &step.template {
+            match t {
+                TItem::Base(b) => self.buf.push(*b as u8),
+                TItem::Ref(n, l) => {
+                    self.buf.push(4);
+                    self.varint(*n);
+                    self.varint(*l);
+                }
+                TItem::RefLen(n) => {
+                    self.buf.push(5);
+                    self.varint(*n);
+                }
+            }
+        }
+    }
+}
+
+impl<W: Write> Tracer for BinaryTracer<W> {
+    fn trace(&mut self, step: &TraceStep) {
+        if self.error.is_some() {
+            return;
+        }
+        self.buf.clear();
+        if !self.started {
+            self.buf.extend_from_slice(BINARY_TRACE_MAGIC);
+            self.started = true;
+        }
+        self.encode(step);
+        self.error = self.out.write_all(&self.buf).err();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step() -> TraceStep {
+        TraceStep {
+            step: 200,
+            outcome: StepOutcome::Matched,
+            pattern: vec![
+                PItem::Open,
+                PItem::Skip(2),
+                PItem::Close,
+                PItem::Search(vec![Base::I, Base::C, Base::F, Base::P, Base::P]),
+                PItem::Base(Base::P),
+            ],
+            template: vec![TItem::Base(Base::F), TItem::Ref(0, 1), TItem::RefLen(0)],
+            consumed: 30,
+            matched: Some(12),
+            envs: vec![2],
+            dna_len: 5,
+        }
+    }
+
+    #[test]
+    fn json_lines() {
+        let mut t = JsonLinesTracer::new(vec![]);
+        t.trace(&step());
+        let mut s = step();
+        s.outcome = StepOutcome::PatternMismatch;
+        s.matched = None;
+        s.envs = vec![];
+        t.trace(&s);
+        let out = String::from_utf8(t.finish().unwrap()).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "{\"step\":200,\"outcome\":\"Matched\",\
+                 \"pattern\":[\"(\",\"!2\",\")\",\"?ICFPP\",\"P\"],\
+                 \"template\":[\"F\",\"\\\\0_1\",\"|0|\"],\
+                 \"consumed\":30,\"matched\":12,\"envs\":[2],\"dna_len\":5}",
+                "{\"step\":200,\"outcome\":\"PatternMismatch\",\
+                 \"pattern\":[\"(\",\"!2\",\")\",\"?ICFPP\",\"P\"],\
+                 \"template\":[\"F\",\"\\\\0_1\",\"|0|\"],\
+                 \"consumed\":30,\"matched\":null,\"envs\":[],\"dna_len\":5}",
+            ]
+        );
+    }
+
+    #[test]
+    fn binary() {
+        let mut t = BinaryTracer::new(vec![]);
+        t.trace(&step());
+        let out = t.finish().unwrap();
+        let mut expected = BINARY_TRACE_MAGIC.to_vec();
+        expected.extend_from_slice(&[
+            0xc8,
+            0x01, // step 200
+            0,
+            30,
+            13,
+            5, // outcome, consumed, matched + 1, dna_len
+            1,
+            2, // envs
+            5,
+            6,
+            4,
+            2,
+            7,
+            5,
+            5,
+            0b1110_0100,
+            0b11,
+            3, // pattern
+            3,
+            2,
+            4,
+            0,
+            1,
+            5,
+            0, // template
+        ]);
+        assert_eq!(out, expected);
+        assert_eq!(
+            BinaryTracer::new(vec![]).finish().unwrap(),
+            BINARY_TRACE_MAGIC
+        );
+    }
+}
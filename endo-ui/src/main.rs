@@ -1,13 +1,21 @@
-use fltk::{app::*, dialog::*, draw::*, frame::*, menu::*, window::Window};
+use fltk::{app::*, button::*, dialog::*, draw::*, frame::*, menu::*, window::Window};
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender};
-use std::{fs, path};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{fs, path, thread};
 
 #[derive(Copy, Clone)]
 pub enum Message {
     StepDNA,
+    StopDNA,
+    /// The DNA thread loaded the DNA, or failed to.
+    DnaLoaded(Result<(), dna2rna::Dna2RnaError>),
+    /// The DNA thread ran a batch, and how many steps there have been.
+    DnaRan(Result<dna2rna::RunOutcome, dna2rna::Dna2RnaError>, usize),
     OpenDNA,
     StepRNA(bool),
     OpenRNA,
@@ -18,44 +26,99 @@ pub enum Message {
 
 struct EndoRnaStore<F>
 where
-    F: Fn(String) -> (),
+    F: Fn(dna2rna::RnaCommand) -> (),
 {
     pub cls: Box<F>,
 }
 
 impl<F> dna2rna::RnaStore for EndoRnaStore<F>
 where
-    F: Fn(String) -> (),
+    F: Fn(dna2rna::RnaCommand) -> (),
 {
-    fn store(&mut self, rna: String) {
+    fn store(&mut self, rna: dna2rna::RnaCommand) {
         (*self.cls)(rna);
     }
 }
 
-struct Endo<'a> {
-    pub d2r: dna2rna::Dna2Rna<'a>,
+/// Work for the DNA thread.
+enum DnaJob {
+    Load(String),
+    Run,
+}
+
+struct Endo {
+    pub dna_jobs: Sender<DnaJob>,
+    /// Set by the Stop button, cleared when new DNA is loaded.
+    pub cancel: Arc<AtomicBool>,
+    /// Whether a `DnaJob::Run` hasn't reported back yet.
+    pub running: bool,
     pub fuun: rna2fuun::Fuun,
     pub step_dna: usize,
     pub steps: usize,
 }
 
-impl<'a> Endo<'a> {
-    pub fn new(rna_store: &'a mut dyn dna2rna::RnaStore) -> Endo<'a> {
+impl Endo {
+    pub fn new(dna_jobs: Sender<DnaJob>, cancel: Arc<AtomicBool>) -> Endo {
         Endo {
-            d2r: dna2rna::Dna2Rna::new(rna_store),
-            fuun: rna2fuun::Fuun::new(""),
+            dna_jobs,
+            cancel,
+            running: false,
+            fuun: rna2fuun::Fuun::new(rna2fuun::Canvas::default()),
             step_dna: 25000,
             steps: 0,
         }
     }
 }
 
+/// Runs DNA jobs off the UI thread, sending RNA to `rna` and reporting back
+/// through `s`.
+fn run_dna(
+    jobs: Receiver<DnaJob>,
+    rna: Sender<dna2rna::RnaCommand>,
+    cancel: Arc<AtomicBool>,
+    step_dna: usize,
+    s: fltk::app::Sender<Message>,
+) {
+    let mut rna_store = EndoRnaStore {
+        cls: Box::new(move |r: dna2rna::RnaCommand| {
+            // the window has gone if this fails
+            let _ = rna.send(r);
+        }),
+    };
+    let mut d2r = dna2rna::Dna2Rna::new(&mut rna_store);
+    for job in jobs {
+        match job {
+            DnaJob::Load(dna) => {
+                // TODO: prefixes
+                //let prefix = Some("IIPIFFCPICICIICPIICIPPPICIIC");
+                let prefix = None;
+                cancel.store(false, Ordering::Relaxed);
+                s.send(Message::DnaLoaded(d2r.set_dna_and_prefix(&dna, prefix)));
+            }
+            DnaJob::Run => {
+                // bound the batch in time too, so the picture keeps updating
+                d2r.set_limits(dna2rna::Limits {
+                    max_steps: Some(step_dna),
+                    deadline: Some(Instant::now() + Duration::from_millis(200)),
+                    cancel: Some(cancel.clone()),
+                    ..dna2rna::Limits::default()
+                });
+                let outcome = d2r.execute();
+                if let Ok(dna2rna::RunOutcome::Done(outcome)) = outcome {
+                    log::info!("{:?}: {:?}", outcome, d2r.stats());
+                }
+                s.send(Message::DnaRan(outcome, d2r.stats().steps()));
+            }
+        }
+    }
+}
+
 fn main() {
     env_logger::init();
 
     let app = App::default().with_scheme(AppScheme::Gtk);
 
-    let (tx, rx): (Sender<[char; 7]>, Receiver<[char; 7]>) = mpsc::channel();
+    let (tx, rx): (Sender<dna2rna::RnaCommand>, Receiver<dna2rna::RnaCommand>) = mpsc::channel();
     let (s, r) = channel::<Message>();
 
     let mut wind = Window::default()
@@ -66,6 +129,9 @@ fn main() {
     let mut menu = MenuBar::new(0, 0, 640, 40, "");
     menu.set_color(Color::Light2);
 
+    let mut stop = Button::new(5, 650, 80, 40, "Stop");
+    stop.emit(s, Message::StopDNA);
+
     let mut frame = Frame::new(5, 40, 600, 600, "");
     frame.set_color(Color::White);
     let frame_c = frame.clone();
@@ -132,23 +198,11 @@ fn main() {
         }
     }));
 
-    let mut rna_store = EndoRnaStore {
-        cls: Box::new(move |rna: String| {
-            assert_eq!(rna.len(), 7);
-            let mut chars = rna.chars();
-            let c = [
-                chars.next().unwrap(),
-                chars.next().unwrap(),
-                chars.next().unwrap(),
-                chars.next().unwrap(),
-                chars.next().unwrap(),
-                chars.next().unwrap(),
-                chars.next().unwrap(),
-            ];
-            tx.send(c).expect("error");
-        }),
-    };
-    let mut endo = Endo::new(&mut rna_store);
+    let (jobs_tx, jobs_rx) = mpsc::channel();
+    let cancel = Arc::new(AtomicBool::new(false));
+    let mut endo = Endo::new(jobs_tx, cancel.clone());
+    let step_dna = endo.step_dna;
+    thread::spawn(move || run_dna(jobs_rx, tx, cancel, step_dna, s));
 
     s.send(Message::Test);
 
@@ -158,19 +212,12 @@ fn main() {
             Some(msg) => match msg {
                 StepRNA(dna) => {
                     log::info!("rna..");
-                    while let Ok(c) = rx.try_recv() {
-                        let mut s = String::new();
-                        s.push(c[0]);
-                        s.push(c[1]);
-                        s.push(c[2]);
-                        s.push(c[3]);
-                        s.push(c[4]);
-                        s.push(c[5]);
-                        s.push(c[6]);
-                        endo.fuun.add_rna_command(s);
+                    while let Ok(rna) = rx.try_recv() {
+                        endo.fuun.add_rna_command(rna);
                     }
                     let rem = endo.fuun.remaining_steps();
-                    let (bmp, done) = endo.fuun.step(rem);
+                    let done = endo.fuun.step(rem).is_done();
+                    let bmp = endo.fuun.current_bitmap();
                     offs.borrow().begin();
                     for y in 0..600 {
                         for x in 0..600 {
@@ -200,27 +247,57 @@ fn main() {
                     }
                     match path::Path::new(&filename).exists() {
                         true => {
-                            let rna = fs::read_to_string(filename).unwrap();
+                            let rna = fs::read(filename).unwrap();
                             endo.fuun.reset();
-                            endo.fuun.add_rna_str(&rna);
-                            s.send(Message::StepRNA(false));
+                            match endo.fuun.add_rna_bytes(&rna) {
+                                Ok(load) => {
+                                    log::info!("loaded rna: {:?}", load);
+                                    s.send(Message::StepRNA(false));
+                                }
+                                Err(e) => alert(200, 200, &format!("Invalid RNA: {}", e)),
+                            }
                         }
                         false => alert(200, 200, "File does not exist!"),
                     }
                 }
                 StepDNA => {
-                    log::info!("dna.. {}", endo.steps);
-                    let mut done = false;
-                    for _ in 0..endo.step_dna {
-                        endo.steps = endo.steps + 1;
-                        if endo.d2r.execute_step() {
-                            done = true;
-                            break;
-                        }
+                    // only one batch at a time, the next is asked for when it's done
+                    if !endo.running {
+                        log::info!("dna.. {}", endo.steps);
+                        endo.running = true;
+                        endo.dna_jobs.send(DnaJob::Run).unwrap();
                     }
+                }
+                StopDNA => endo.cancel.store(true, Ordering::Relaxed),
+                DnaRan(outcome, steps) => {
+                    endo.running = false;
+                    endo.steps = steps;
+                    let done = match outcome {
+                        Ok(dna2rna::RunOutcome::Done(_)) => true,
+                        Ok(dna2rna::RunOutcome::Limited(dna2rna::Limit::Cancelled)) => {
+                            log::info!("dna stopped at step {}", steps);
+                            true
+                        }
+                        Ok(dna2rna::RunOutcome::Limited(_)) => false,
+                        Err(e) => {
+                            log::error!("dna error at step {}: {}", steps, e);
+                            alert(200, 200, &format!("DNA error: {}", e));
+                            true
+                        }
+                    };
                     s.send(Message::StepRNA(!done));
                     log::info!("..dna");
                 }
+                DnaLoaded(loaded) => match loaded {
+                    Ok(()) => {
+                        // drop what the DNA loaded before sent
+                        while rx.try_recv().is_ok() {}
+                        endo.fuun.reset();
+                        endo.steps = 0;
+                        s.send(Message::StepDNA);
+                    }
+                    Err(e) => alert(200, 200, &format!("Invalid DNA: {}", e)),
+                },
                 OpenDNA => {
                     let mut dlg = FileDialog::new(FileDialogType::BrowseFile);
                     dlg.set_option(FileDialogOptions::NoOptions);
@@ -233,13 +310,9 @@ fn main() {
                     match path::Path::new(&filename).exists() {
                         true => {
                             let dna = fs::read_to_string(filename).unwrap();
-                            // TODO: prefixes
-                            //let prefix = Some("IIPIFFCPICICIICPIICIPPPICIIC");
-                            let prefix = None;
-                            endo.d2r.set_dna_and_prefix(&dna, prefix);
-                            endo.fuun.reset();
-                            endo.steps = 0;
-                            s.send(Message::StepDNA);
+                            // stop the DNA running now, the thread loads this next
+                            endo.cancel.store(true, Ordering::Relaxed);
+                            endo.dna_jobs.send(DnaJob::Load(dna)).unwrap();
                         }
                         false => alert(200, 200, "File does not exist!"),
                     }
@@ -0,0 +1,75 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use dna2rna::RnaCommand;
+use rna2fuun::{Canvas, Pixel, Rgb};
+
+mod common;
+
+const ROUNDS: usize = 300;
+
+/// RNA that composes and clips a fresh bitmap onto the stack `n` times.
+fn blends(n: usize) -> Vec<RnaCommand> {
+    let mut rna = vec![];
+    for _ in 0..n {
+        rna.push(RnaCommand::AddBitmap);
+        rna.push(RnaCommand::Compose);
+        rna.push(RnaCommand::AddBitmap);
+        rna.push(RnaCommand::Clip);
+    }
+    rna
+}
+
+/// The same as `blends` with the nested loops compose and clip used to be.
+fn naive_blends(n: usize) -> Pixel {
+    let canvas = Canvas::default();
+    let mut below = common::transparent(canvas);
+    for _ in 0..n {
+        let top = common::transparent(canvas);
+        for y in 0..canvas.height {
+            for x in 0..canvas.width {
+                let ix = y * canvas.width + x;
+                let p0 = top.pixels[ix];
+                let p1 = below.pixels[ix];
+                let a0 = p0.alpha as usize;
+                let over = |c0: u8, c1: u8| (c0 as usize + c1 as usize * (255 - a0) / 255) as u8;
+                below.pixels[ix] = Pixel {
+                    color: Rgb {
+                        r: over(p0.color.r, p1.color.r),
+                        g: over(p0.color.g, p1.color.g),
+                        b: over(p0.color.b, p1.color.b),
+                    },
+                    alpha: over(p0.alpha, p1.alpha),
+                };
+            }
+        }
+        let top = common::transparent(canvas);
+        for y in 0..canvas.height {
+            for x in 0..canvas.width {
+                let ix = y * canvas.width + x;
+                let a0 = top.pixels[ix].alpha as usize;
+                let p1 = below.pixels[ix];
+                let scale = |c1: u8| (c1 as usize * a0 / 255) as u8;
+                below.pixels[ix] = Pixel {
+                    color: Rgb {
+                        r: scale(p1.color.r),
+                        g: scale(p1.color.g),
+                        b: scale(p1.color.b),
+                    },
+                    alpha: scale(p1.alpha),
+                };
+            }
+        }
+    }
+    below.pixels[0]
+}
+
+fn blend(c: &mut Criterion) {
+    let mut group = c.benchmark_group("blend");
+    group.sample_size(10);
+    let rna = blends(ROUNDS);
+    group.bench_function("compose_clip", |b| b.iter(|| common::draw(&rna)));
+    group.bench_function("naive", |b| b.iter(|| naive_blends(ROUNDS)));
+    group.finish();
+}
+
+criterion_group!(benches, blend);
+criterion_main!(benches);
@@ -0,0 +1,110 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use dna2rna::RnaCommand;
+use rna2fuun::{Canvas, Pixel, Rgb};
+
+mod common;
+
+/// RNA that adds `n` colors and draws a two pixel line after each one, so
+/// the current pixel is worked out again for every color.
+fn growing_bucket(n: usize) -> Vec<RnaCommand> {
+    let colors = [
+        RnaCommand::AddRed,
+        RnaCommand::AddCyan,
+        RnaCommand::AddOpaque,
+        RnaCommand::AddTransparent,
+    ];
+    let mut rna = vec![];
+    for i in 0..n {
+        rna.push(colors[i % colors.len()]);
+        rna.push(RnaCommand::Mark);
+        rna.push(RnaCommand::Move);
+        rna.push(RnaCommand::Line);
+    }
+    rna
+}
+
+enum Color {
+    Rgb(Rgb),
+    Transparency(u8),
+}
+
+/// The average of the colors in `bucket` the way it used to be worked out,
+/// going through all of them.
+fn naive_current(bucket: &[Color]) -> Pixel {
+    let (mut r, mut g, mut b, mut colors) = (0usize, 0usize, 0usize, 0usize);
+    let (mut alpha, mut alphas) = (0usize, 0usize);
+    for c in bucket {
+        match c {
+            Color::Rgb(rgb) => {
+                r += rgb.r as usize;
+                g += rgb.g as usize;
+                b += rgb.b as usize;
+                colors += 1;
+            }
+            Color::Transparency(a) => {
+                alpha += *a as usize;
+                alphas += 1;
+            }
+        }
+    }
+    let (rc, gc, bc) = match colors {
+        0 => (0, 0, 0),
+        n => (r / n, g / n, b / n),
+    };
+    let ac = match alphas {
+        0 => 255,
+        n => alpha / n,
+    };
+    Pixel {
+        color: Rgb {
+            r: (rc * ac / 255) as u8,
+            g: (gc * ac / 255) as u8,
+            b: (bc * ac / 255) as u8,
+        },
+        alpha: ac as u8,
+    }
+}
+
+/// `growing_bucket` with the bucket as the list of colors it used to be,
+/// each line drawn straight into the bitmap along the top row.
+fn naive_lines(n: usize) -> Pixel {
+    let canvas = Canvas::default();
+    let mut bmp = common::transparent(canvas);
+    let mut bucket = vec![];
+    for i in 0..n {
+        let color = match i % 4 {
+            0 => Color::Rgb(Rgb { r: 255, g: 0, b: 0 }),
+            1 => Color::Rgb(Rgb {
+                r: 0,
+                g: 255,
+                b: 255,
+            }),
+            2 => Color::Transparency(255),
+            _ => Color::Transparency(0),
+        };
+        bucket.insert(0, color);
+        let p = naive_current(&bucket);
+        let x = i % canvas.width;
+        bmp.pixels[x] = p;
+        bmp.pixels[(x + 1) % canvas.width] = p;
+    }
+    bmp.pixels[0]
+}
+
+fn bucket(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bucket");
+    group.sample_size(10);
+    for n in [1_000, 10_000].iter() {
+        let rna = growing_bucket(*n);
+        group.bench_with_input(BenchmarkId::new("lines", n), &rna, |b, rna| {
+            b.iter(|| common::draw(rna))
+        });
+        group.bench_with_input(BenchmarkId::new("naive", n), n, |b, n| {
+            b.iter(|| naive_lines(*n))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bucket);
+criterion_main!(benches);
@@ -0,0 +1,28 @@
+//! What the benches share.
+
+use criterion::black_box;
+use dna2rna::RnaCommand;
+use rna2fuun::{Bitmap, Canvas, Fuun, Pixel, Rgb};
+
+/// Draws `rna` on a default canvas, returning a pixel of the result so
+/// the drawing can't be optimized away.
+pub fn draw(rna: &[RnaCommand]) -> Pixel {
+    let mut fuun = Fuun::new(Canvas::default());
+    for r in rna {
+        fuun.add_rna_command(*r);
+    }
+    fuun.build().pixels[0]
+}
+
+/// A fresh bitmap as `AddBitmap` makes, hidden from the optimizer so it
+/// can't use that it's all zeros.
+pub fn transparent(canvas: Canvas) -> Bitmap {
+    let black = Pixel {
+        color: Rgb { r: 0, g: 0, b: 0 },
+        alpha: 0,
+    };
+    black_box(Bitmap {
+        canvas,
+        pixels: vec![black; canvas.pixels()],
+    })
+}
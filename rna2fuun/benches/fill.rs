@@ -0,0 +1,94 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use dna2rna::RnaCommand;
+use rna2fuun::{Canvas, Pixel, Rgb};
+
+mod common;
+
+/// RNA that fills the whole canvas `n` times, alternating two colors, after
+/// drawing a diagonal line the fills have to go around.
+fn refills(n: usize) -> Vec<RnaCommand> {
+    let mut rna = vec![RnaCommand::AddWhite, RnaCommand::Mark];
+    for _ in 0..300 {
+        rna.push(RnaCommand::Move);
+    }
+    rna.push(RnaCommand::TurnClockwise);
+    for _ in 0..300 {
+        rna.push(RnaCommand::Move);
+    }
+    rna.push(RnaCommand::Line);
+    // step off the line
+    rna.push(RnaCommand::TurnClockwise);
+    for _ in 0..10 {
+        rna.push(RnaCommand::Move);
+    }
+    rna.push(RnaCommand::ClearBucket);
+    for i in 0..n {
+        rna.push(match i % 2 {
+            0 => RnaCommand::AddRed,
+            _ => RnaCommand::AddBlue,
+        });
+        rna.push(RnaCommand::TryFill);
+        rna.push(RnaCommand::ClearBucket);
+    }
+    rna
+}
+
+fn opaque(r: u8, g: u8, b: u8) -> Pixel {
+    Pixel {
+        color: Rgb { r, g, b },
+        alpha: 255,
+    }
+}
+
+/// `refills` with the fill from before the scanline one, a pixel at a time.
+fn naive_refills(n: usize) -> Pixel {
+    let canvas = Canvas::default();
+    let (width, height) = (canvas.width, canvas.height);
+    let mut bmp = common::transparent(canvas);
+    for i in 0..=300 {
+        bmp.pixels[i * width + i] = opaque(255, 255, 255);
+    }
+    let mut todo = vec![];
+    for i in 0..n {
+        let new = match i % 2 {
+            0 => opaque(255, 0, 0),
+            _ => opaque(0, 0, 255),
+        };
+        let initial = bmp.pixels[300 * width + 290];
+        if initial == new {
+            continue;
+        }
+        todo.push((290, 300));
+        while let Some((x, y)) = todo.pop() {
+            let ix = y * width + x;
+            if bmp.pixels[ix] == initial {
+                bmp.pixels[ix] = new;
+                if x > 0 {
+                    todo.push((x - 1, y));
+                }
+                if x < width - 1 {
+                    todo.push((x + 1, y));
+                }
+                if y > 0 {
+                    todo.push((x, y - 1));
+                }
+                if y < height - 1 {
+                    todo.push((x, y + 1));
+                }
+            }
+        }
+    }
+    bmp.pixels[0]
+}
+
+fn fill(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fill");
+    group.sample_size(10);
+    let rna = refills(10);
+    group.bench_function("refills", |b| b.iter(|| common::draw(&rna)));
+    group.bench_function("naive", |b| b.iter(|| naive_refills(10)));
+    group.finish();
+}
+
+criterion_group!(benches, fill);
+criterion_main!(benches);
@@ -0,0 +1,277 @@
+//! Converting bitmaps to and from image files.
+//!
+//! Pixels are exported as stored, with the colors already multiplied by the
+//! alpha, so the RGB channels are the picture as the spec draws it.
+
+use crate::{Bitmap, Canvas, Pixel, Rgb};
+use std::io::{self, Read, Write};
+
+impl Bitmap {
+    /// The pixels as `r g b a` bytes, row by row from the top left.
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.pixels.len() * 4);
+        for p in &self.pixels {
+            data.extend_from_slice(&[p.color.r, p.color.g, p.color.b, p.alpha]);
+        }
+        data
+    }
+
+    /// A bitmap from `r g b a` bytes laid out as by `to_rgba8`, or `None`
+    /// if there aren't exactly enough for a whole bitmap of size `canvas`.
+    pub fn from_rgba8(canvas: Canvas, data: &[u8]) -> Option<Bitmap> {
+        if data.len() != canvas.pixels() * 4 {
+            return None;
+        }
+        let pixels = data
+            .chunks(4)
+            .map(|c| Pixel {
+                color: Rgb {
+                    r: c[0],
+                    g: c[1],
+                    b: c[2],
+                },
+                alpha: c[3],
+            })
+            .collect();
+        Some(Bitmap { canvas, pixels })
+    }
+
+    fn to_rgb8(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.pixels.len() * 3);
+        for p in &self.pixels {
+            data.extend_from_slice(&[p.color.r, p.color.g, p.color.b]);
+        }
+        data
+    }
+
+    /// Writes the colors as a binary PPM, dropping the alpha.
+    pub fn write_ppm<W: Write>(&self, mut out: W) -> io::Result<()> {
+        write!(
+            out,
+            "P6\n{} {}\n255\n",
+            self.canvas.width, self.canvas.height
+        )?;
+        out.write_all(&self.to_rgb8())?;
+        out.flush()
+    }
+
+    /// Writes a PNG, with an alpha channel if `alpha` is set.
+    pub fn write_png<W: Write>(&self, out: W, alpha: bool) -> io::Result<()> {
+        let mut encoder =
+            png::Encoder::new(out, self.canvas.width as u32, self.canvas.height as u32);
+        encoder.set_depth(png::BitDepth::Eight);
+        let data = if alpha {
+            encoder.set_color(png::ColorType::RGBA);
+            self.to_rgba8()
+        } else {
+            encoder.set_color(png::ColorType::RGB);
+            self.to_rgb8()
+        };
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        Ok(())
+    }
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what)
+}
+
+/// Splits the next whitespace separated field off a PPM header, skipping
+/// comments.
+fn ppm_field<'d>(data: &mut &'d [u8]) -> io::Result<&'d [u8]> {
+    loop {
+        match data.first() {
+            Some(b'#') => {
+                let end = data.iter().position(|b| *b == b'\n').unwrap_or(data.len());
+                *data = &data[end..];
+            }
+            Some(b) if b.is_ascii_whitespace() => *data = &data[1..],
+            Some(_) => break,
+            None => return Err(invalid("ppm ends inside the header")),
+        }
+    }
+    let end = data
+        .iter()
+        .position(|b| b.is_ascii_whitespace())
+        .unwrap_or(data.len());
+    let field = &data[..end];
+    *data = &data[end..];
+    Ok(field)
+}
+
+impl Bitmap {
+    /// Reads a binary PPM, all pixels opaque.
+    pub fn read_ppm<R: Read>(mut input: R) -> io::Result<Bitmap> {
+        let mut buf = vec![];
+        input.read_to_end(&mut buf)?;
+        let mut data = &buf[..];
+        if ppm_field(&mut data)? != b"P6" {
+            return Err(invalid("not a binary ppm"));
+        }
+        let mut numbers = [0; 3];
+        for n in numbers.iter_mut() {
+            let field = std::str::from_utf8(ppm_field(&mut data)?).ok();
+            *n = field
+                .and_then(|f| f.parse().ok())
+                .ok_or_else(|| invalid("bad number in ppm header"))?;
+        }
+        if numbers[2] != 255 {
+            return Err(invalid("ppm doesn't have 8 bit colors"));
+        }
+        let canvas = Canvas {
+            width: numbers[0],
+            height: numbers[1],
+        };
+        // a single whitespace byte separates the header from the pixels
+        let rgb = &data[1.min(data.len())..];
+        let len = canvas
+            .width
+            .checked_mul(canvas.height)
+            .and_then(|n| n.checked_mul(3));
+        if Some(rgb.len()) != len {
+            return Err(invalid("ppm has the wrong amount of pixel data"));
+        }
+        let mut rgba = Vec::with_capacity(canvas.pixels() * 4);
+        for c in rgb.chunks(3) {
+            rgba.extend_from_slice(&[c[0], c[1], c[2], 255]);
+        }
+        Ok(Bitmap::from_rgba8(canvas, &rgba).unwrap())
+    }
+
+    /// Reads a PNG in any of its color types and bit depths.
+    pub fn read_png<R: Read>(input: R) -> io::Result<Bitmap> {
+        let mut decoder = png::Decoder::new(input);
+        // palettes and small samples expanded to bytes, 16 bit ones cut to 8
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let (info, mut reader) = decoder.read_info()?;
+        let canvas = Canvas {
+            width: info.width as usize,
+            height: info.height as usize,
+        };
+        let mut data = vec![0; info.buffer_size()];
+        reader.next_frame(&mut data)?;
+        let mut rgba = Vec::with_capacity(canvas.pixels() * 4);
+        match info.color_type {
+            png::ColorType::RGBA => rgba = data,
+            png::ColorType::RGB => {
+                for c in data.chunks(3) {
+                    rgba.extend_from_slice(&[c[0], c[1], c[2], 255]);
+                }
+            }
+            png::ColorType::GrayscaleAlpha => {
+                for c in data.chunks(2) {
+                    rgba.extend_from_slice(&[c[0], c[0], c[0], c[1]]);
+                }
+            }
+            png::ColorType::Grayscale => {
+                for c in data.iter() {
+                    rgba.extend_from_slice(&[*c, *c, *c, 255]);
+                }
+            }
+            png::ColorType::Indexed => return Err(invalid("png palette wasn't expanded")),
+        }
+        Bitmap::from_rgba8(canvas, &rgba)
+            .ok_or_else(|| invalid("png has the wrong amount of pixel data"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 600;
+    const HEIGHT: usize = 400;
+
+    fn bitmap() -> Bitmap {
+        let mut bmp = Bitmap::new(Canvas {
+            width: WIDTH,
+            height: HEIGHT,
+        });
+        bmp.pixels[1] = Pixel::new(1, 2, 3, 4);
+        bmp.pixels[WIDTH * HEIGHT - 1] = Pixel::new(255, 0, 0, 255);
+        bmp
+    }
+
+    #[test]
+    fn rgba8() {
+        let bmp = bitmap();
+        let data = bmp.to_rgba8();
+        assert_eq!(&data[..8], &[0, 0, 0, 0, 1, 2, 3, 4]);
+        assert_eq!(
+            Bitmap::from_rgba8(bmp.canvas, &data).unwrap().pixels,
+            bmp.pixels
+        );
+        assert!(Bitmap::from_rgba8(bmp.canvas, &data[4..]).is_none());
+    }
+
+    #[test]
+    fn ppm() {
+        let mut out = vec![];
+        bitmap().write_ppm(&mut out).unwrap();
+        let header = b"P6\n600 400\n255\n";
+        assert_eq!(&out[..header.len()], header);
+        assert_eq!(out.len(), header.len() + WIDTH * HEIGHT * 3);
+        assert_eq!(&out[header.len()..header.len() + 6], &[0, 0, 0, 1, 2, 3]);
+        assert_eq!(&out[out.len() - 3..], &[255, 0, 0]);
+        let read = Bitmap::read_ppm(&out[..]).unwrap();
+        assert_eq!(read.pixels[1], Pixel::new(1, 2, 3, 255));
+        assert_eq!(read.pixels[WIDTH * HEIGHT - 1], Pixel::new(255, 0, 0, 255));
+        let mut commented = b"P6 # made by hand\n600 400\n255\n".to_vec();
+        commented.extend_from_slice(&out[header.len()..]);
+        assert_eq!(
+            Bitmap::read_ppm(&commented[..]).unwrap().pixels,
+            read.pixels
+        );
+        assert_eq!(
+            Bitmap::read_ppm(&out[..out.len() - 1])
+                .err()
+                .map(|e| e.kind()),
+            Some(io::ErrorKind::InvalidData)
+        );
+        assert_eq!(
+            Bitmap::read_ppm(&b"P3\n600 600\n255\n"[..])
+                .err()
+                .map(|e| e.kind()),
+            Some(io::ErrorKind::InvalidData)
+        );
+    }
+
+    #[test]
+    fn png() {
+        for &alpha in &[false, true] {
+            let mut out = vec![];
+            bitmap().write_png(&mut out, alpha).unwrap();
+            let (info, mut reader) = png::Decoder::new(&out[..]).read_info().unwrap();
+            assert_eq!((info.width, info.height), (600, 400));
+            let mut data = vec![0; info.buffer_size()];
+            reader.next_frame(&mut data).unwrap();
+            let read = Bitmap::read_png(&out[..]).unwrap();
+            if alpha {
+                assert_eq!(data, bitmap().to_rgba8());
+                assert_eq!(read.pixels, bitmap().pixels);
+            } else {
+                assert_eq!(data, bitmap().to_rgb8());
+                assert_eq!(read.pixels[1], Pixel::new(1, 2, 3, 255));
+            }
+        }
+    }
+
+    #[test]
+    fn png_16() {
+        // each 8 bit sample as the high byte of a 16 bit one
+        let mut data = vec![];
+        for b in bitmap().to_rgba8() {
+            data.extend_from_slice(&[b, 0x80]);
+        }
+        let mut out = vec![];
+        {
+            let mut encoder = png::Encoder::new(&mut out, WIDTH as u32, HEIGHT as u32);
+            encoder.set_color(png::ColorType::RGBA);
+            encoder.set_depth(png::BitDepth::Sixteen);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&data).unwrap();
+        }
+        assert_eq!(Bitmap::read_png(&out[..]).unwrap().pixels, bitmap().pixels);
+    }
+}
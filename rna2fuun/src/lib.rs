@@ -1,4 +1,10 @@
+use dna2rna::{Base, RnaCommand, RNA_LEN};
 use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+
+mod image;
+pub mod score;
 
 type Coord = i32;
 
@@ -61,15 +67,40 @@ impl Pixel {
     }
 }
 
+/// Size of the bitmaps drawn on, 600 x 600 by default as in the spec.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Canvas {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Canvas {
+    pub fn pixels(&self) -> usize {
+        self.width * self.height
+    }
+}
+
+impl Default for Canvas {
+    fn default() -> Canvas {
+        Canvas {
+            width: 600,
+            height: 600,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Bitmap {
-    pub pixels: Vec<Pixel>, // 600 x 600
+    pub canvas: Canvas,
+    /// Row by row from the top left.
+    pub pixels: Vec<Pixel>,
 }
 
 impl Bitmap {
-    fn new() -> Bitmap {
+    fn new(canvas: Canvas) -> Bitmap {
         Bitmap {
-            pixels: vec![Pixel::new(0, 0, 0, TRANSPARENT); 360000],
+            canvas,
+            pixels: vec![Pixel::new(0, 0, 0, TRANSPARENT); canvas.pixels()],
         }
     }
 }
@@ -80,6 +111,18 @@ enum Color {
     Transparency(Transparency),
 }
 
+/// Running totals of the colors added to the bucket, which is all that's
+/// needed to average them.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+struct Bucket {
+    r: usize,
+    g: usize,
+    b: usize,
+    colors: usize,
+    alpha: usize,
+    alphas: usize,
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 enum Dir {
     N,
@@ -88,62 +131,155 @@ enum Dir {
     W,
 }
 
+/// Errors loading RNA text, `offset` is in bytes.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RnaLoadError {
+    /// A byte that is neither a base nor whitespace.
+    InvalidByte { offset: usize, found: u8 },
+    /// The text ends with only `len` bases of a command.
+    PartialCommand { offset: usize, len: usize },
+}
+
+impl fmt::Display for RnaLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RnaLoadError::InvalidByte { offset, found } => {
+                write!(f, "invalid byte {:#04x} at offset {}", found, offset)
+            }
+            RnaLoadError::PartialCommand { offset, len } => write!(
+                f,
+                "rna ends inside a command of {} bases at offset {}",
+                len, offset
+            ),
+        }
+    }
+}
+
+impl Error for RnaLoadError {}
+
+/// What loading some RNA text added.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct RnaLoad {
+    pub commands: usize,
+    /// Commands made of valid bases that aren't known, which do nothing.
+    pub unknown: usize,
+    /// Whitespace bytes that were skipped.
+    pub whitespace: usize,
+}
+
+/// How far through its RNA a `Fuun` is.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Progress {
+    /// Commands run since the last reset.
+    pub steps: usize,
+    /// Commands added since the last reset.
+    pub total: usize,
+}
+
+impl Progress {
+    pub fn is_done(&self) -> bool {
+        self.steps == self.total
+    }
+}
+
 pub struct Fuun {
-    rna: Vec<String>,
-    bucket: Vec<Color>,
+    canvas: Canvas,
+    rna: Vec<RnaCommand>,
+    bucket: Bucket,
     position: Pos,
     mark: Pos,
     dir: Dir,
     bitmaps: VecDeque<Bitmap>,
     current: Option<Pixel>,
     fill_todo: Vec<Pos>,
+    filled: usize,
     step: usize,
 }
 
 impl Fuun {
-    pub fn new(rna_str: &str) -> Fuun {
+    /// A renderer drawing on bitmaps of size `canvas`, widened to at least
+    /// 1 x 1 as there would be nowhere to draw otherwise.
+    pub fn new(canvas: Canvas) -> Fuun {
+        let canvas = Canvas {
+            width: canvas.width.max(1),
+            height: canvas.height.max(1),
+        };
         let mut bitmaps = VecDeque::new();
-        bitmaps.push_front(Bitmap::new());
-        let mut f = Fuun {
+        bitmaps.push_front(Bitmap::new(canvas));
+        Fuun {
+            canvas,
             rna: vec![],
-            bucket: vec![],
+            bucket: Bucket::default(),
             position: Pos { x: 0, y: 0 },
             mark: Pos { x: 0, y: 0 },
             dir: Dir::E,
             bitmaps,
             current: None,
             fill_todo: vec![],
+            filled: 0,
             step: 0,
-        };
-        f.fill_todo.reserve(360000);
-        f.add_rna_str(rna_str);
-        f
+        }
     }
 
     pub fn reset(&mut self) {
         self.rna.clear();
-        self.bucket.clear();
+        self.bucket = Bucket::default();
         self.position = Pos { x: 0, y: 0 };
         self.mark = self.position;
         self.dir = Dir::E;
         self.bitmaps.clear();
-        self.bitmaps.push_front(Bitmap::new());
+        self.bitmaps.push_front(Bitmap::new(self.canvas));
         self.current = None;
         self.fill_todo.clear();
+        self.filled = 0;
         self.step = 0;
     }
 
-    pub fn add_rna_command(&mut self, rna: String) {
-        assert!(rna.len() == 7);
+    pub fn add_rna_command(&mut self, rna: RnaCommand) {
         self.rna.push(rna);
     }
 
-    pub fn add_rna_str(&mut self, rna_str: &str) {
-        for s in (0..rna_str.len()).step_by(7) {
-            let e = std::cmp::min(s + 7, rna_str.len());
-            let code = &rna_str[s..e];
-            self.add_rna_command(code.into());
+    /// Adds the commands in `rna`, skipping whitespace. Nothing is added if
+    /// there's an invalid byte or a partial command at the end.
+    pub fn add_rna_bytes(&mut self, rna: &[u8]) -> Result<RnaLoad, RnaLoadError> {
+        let mut load = RnaLoad::default();
+        let mut commands = vec![];
+        let mut bases = [Base::I; RNA_LEN];
+        let mut len = 0;
+        let mut start = 0;
+        for (offset, byte) in rna.iter().enumerate() {
+            if byte.is_ascii_whitespace() {
+                load.whitespace += 1;
+                continue;
+            }
+            let base = Base::from_char(*byte as char).ok_or(RnaLoadError::InvalidByte {
+                offset,
+                found: *byte,
+            })?;
+            if len == 0 {
+                start = offset;
+            }
+            bases[len] = base;
+            len += 1;
+            if len == RNA_LEN {
+                let command = RnaCommand::from_bases(bases);
+                if let RnaCommand::Unknown(_) = command {
+                    load.unknown += 1;
+                }
+                commands.push(command);
+                len = 0;
+            }
         }
+        if len > 0 {
+            return Err(RnaLoadError::PartialCommand { offset: start, len });
+        }
+        load.commands = commands.len();
+        self.rna.extend(commands);
+        Ok(load)
+    }
+
+    pub fn add_rna_str(&mut self, rna_str: &str) -> Result<RnaLoad, RnaLoadError> {
+        self.add_rna_bytes(rna_str.as_bytes())
     }
 
     pub fn remaining_steps(&self) -> usize {
@@ -152,41 +288,34 @@ impl Fuun {
 
     fn add_color(&mut self, color: Color) {
         self.current = None;
-        self.bucket.insert(0, color);
+        let bucket = &mut self.bucket;
+        match color {
+            Color::Rgb(rgb) => {
+                bucket.r += rgb.r as usize;
+                bucket.g += rgb.g as usize;
+                bucket.b += rgb.b as usize;
+                bucket.colors += 1;
+            }
+            Color::Transparency(alpha) => {
+                bucket.alpha += alpha as usize;
+                bucket.alphas += 1;
+            }
+        }
     }
 
     fn current_pixel(&mut self) -> Pixel {
         if let Some(pixel) = self.current {
             return pixel;
         }
-        let mut rsum = 0usize;
-        let mut rcnt = 0usize;
-        let mut gsum = 0usize;
-        let mut gcnt = 0usize;
-        let mut bsum = 0usize;
-        let mut bcnt = 0usize;
-        let mut asum = 0usize;
-        let mut acnt = 0usize;
-        for c in &self.bucket {
-            match c {
-                Color::Rgb(rgb) => {
-                    rsum = rsum + rgb.r as usize;
-                    rcnt = rcnt + 1;
-                    gsum = gsum + rgb.g as usize;
-                    gcnt = gcnt + 1;
-                    bsum = bsum + rgb.b as usize;
-                    bcnt = bcnt + 1;
-                }
-                Color::Transparency(alpha) => {
-                    asum = asum + *alpha as usize;
-                    acnt = acnt + 1;
-                }
-            }
-        }
-        let rc = if rcnt > 0 { rsum / rcnt } else { 0 };
-        let gc = if gcnt > 0 { gsum / gcnt } else { 0 };
-        let bc = if bcnt > 0 { bsum / bcnt } else { 0 };
-        let ac = if acnt > 0 { asum / acnt } else { 255 };
+        let bucket = &self.bucket;
+        let (rc, gc, bc) = match bucket.colors {
+            0 => (0, 0, 0),
+            n => (bucket.r / n, bucket.g / n, bucket.b / n),
+        };
+        let ac = match bucket.alphas {
+            0 => 255,
+            n => bucket.alpha / n,
+        };
         let p = Pixel::new(
             ((rc * ac) / 255) as Component,
             ((gc * ac) / 255) as Component,
@@ -197,7 +326,8 @@ impl Fuun {
         p
     }
 
-    fn move_dir(pos: Pos, d: Dir) -> Pos {
+    fn move_dir(&self, pos: Pos, d: Dir) -> Pos {
+        let (width, height) = (self.canvas.width as Coord, self.canvas.height as Coord);
         let mut y = pos.y;
         let mut x = pos.x;
         match d {
@@ -207,13 +337,13 @@ impl Fuun {
             Dir::W => x = x - 1,
         }
         if y < 0 {
-            y = 599;
-        } else if y > 599 {
+            y = height - 1;
+        } else if y >= height {
             y = 0;
         }
         if x < 0 {
-            x = 599;
-        } else if x > 599 {
+            x = width - 1;
+        } else if x >= width {
             x = 0;
         }
         Pos { x, y }
@@ -238,12 +368,12 @@ impl Fuun {
     }
 
     fn get_pixel(&self, p: Pos) -> Pixel {
-        let ix = (p.y * 600 + p.x) as usize;
+        let ix = p.y as usize * self.canvas.width + p.x as usize;
         self.bitmaps[0].pixels[ix]
     }
 
     fn set_pixel(&mut self, p: Pos) {
-        let ix = (p.y * 600 + p.x) as usize;
+        let ix = p.y as usize * self.canvas.width + p.x as usize;
         self.bitmaps[0].pixels[ix] = self.current_pixel();
     }
 
@@ -267,140 +397,204 @@ impl Fuun {
         let new = self.current_pixel();
         let old = self.get_pixel(self.position);
         if new != old {
-            self.fill(self.position, old);
+            let filled = self.fill(self.position, old);
+            self.filled += filled;
         }
     }
 
-    fn fill(&mut self, pos: Pos, initial: Pixel) {
+    /// Fills the area of `initial` pixels around `pos` with the current
+    /// pixel a horizontal span at a time, returning the pixel count.
+    fn fill(&mut self, pos: Pos, initial: Pixel) -> usize {
+        let new = self.current_pixel();
+        let (width, height) = (self.canvas.width, self.canvas.height as Coord);
+        let pixels = &mut self.bitmaps[0].pixels;
+        let mut filled = 0;
         self.fill_todo.push(pos);
         while let Some(p) = self.fill_todo.pop() {
-            if self.get_pixel(p) == initial {
-                self.set_pixel(p);
-                if p.x > 0 {
-                    self.fill_todo.push(Pos { x: p.x - 1, y: p.y });
-                }
-                if p.x < 599 {
-                    self.fill_todo.push(Pos { x: p.x + 1, y: p.y });
-                }
-                if p.y > 0 {
-                    self.fill_todo.push(Pos { x: p.x, y: p.y - 1 });
+            let row = p.y as usize * width;
+            if pixels[row + p.x as usize] != initial {
+                continue;
+            }
+            let mut x0 = p.x as usize;
+            while x0 > 0 && pixels[row + x0 - 1] == initial {
+                x0 -= 1;
+            }
+            let mut x1 = p.x as usize;
+            while x1 < width - 1 && pixels[row + x1 + 1] == initial {
+                x1 += 1;
+            }
+            for pixel in &mut pixels[row + x0..=row + x1] {
+                *pixel = new;
+            }
+            filled = filled + x1 - x0 + 1;
+            // queue one seed for every run of the area above and below
+            for &y in &[p.y - 1, p.y + 1] {
+                if y < 0 || y >= height {
+                    continue;
                 }
-                if p.y < 599 {
-                    self.fill_todo.push(Pos { x: p.x, y: p.y + 1 });
+                let row = y as usize * width;
+                let mut in_run = false;
+                for (x, pixel) in pixels[row + x0..=row + x1].iter().enumerate() {
+                    let inside = *pixel == initial;
+                    if inside && !in_run {
+                        self.fill_todo.push(Pos {
+                            x: (x0 + x) as Coord,
+                            y,
+                        });
+                    }
+                    in_run = inside;
                 }
             }
         }
+        filled
+    }
+
+    /// Number of pixels changed by fills since the last reset.
+    pub fn filled_pixels(&self) -> usize {
+        self.filled
     }
 
     fn add_bitmap(&mut self) {
         if self.bitmaps.len() < 10 {
-            self.bitmaps.push_front(Bitmap::new());
+            self.bitmaps.push_front(Bitmap::new(self.canvas));
         }
     }
 
-    fn compose(&mut self) {
+    /// Replaces the second bitmap with `blend` of the top one and it, a
+    /// pixel at a time, and drops the top one.
+    ///
+    /// Generic rather than a function pointer so `blend` is inlined into the
+    /// loop and its channel arithmetic can be vectorized.
+    fn blend<F: Fn(Pixel, Pixel) -> Pixel + Sync>(&mut self, blend: F) {
         if self.bitmaps.len() >= 2 {
-            for y in 0..600 {
-                for x in 0..600 {
-                    let ix = (y * 600 + x) as usize;
-                    let pixel0 = self.bitmaps[0].pixels[ix];
-                    let r0 = pixel0.color.r as usize;
-                    let g0 = pixel0.color.g as usize;
-                    let b0 = pixel0.color.b as usize;
-                    let a0 = pixel0.alpha as usize;
-                    let pixel1 = self.bitmaps[1].pixels[ix];
-                    let r1 = pixel1.color.r as usize;
-                    let g1 = pixel1.color.g as usize;
-                    let b1 = pixel1.color.b as usize;
-                    let a1 = pixel1.alpha as usize;
-                    let pixel = Pixel::new(
-                        (r0 + r1 * (255 - a0) / 255) as Component,
-                        (g0 + g1 * (255 - a0) / 255) as Component,
-                        (b0 + b1 * (255 - a0) / 255) as Component,
-                        (a0 + a1 * (255 - a0) / 255) as Transparency,
-                    );
-                    self.bitmaps[1].pixels[ix] = pixel;
-                }
+            let top = self.bitmaps.pop_front().unwrap();
+            let below = &mut self.bitmaps[0].pixels;
+            #[cfg(feature = "parallel")]
+            let width = self.canvas.width;
+            #[cfg(feature = "parallel")]
+            {
+                use rayon::prelude::*;
+                below
+                    .par_chunks_mut(width)
+                    .zip(top.pixels.par_chunks(width))
+                    .for_each(|(below, top)| {
+                        for (p1, p0) in below.iter_mut().zip(top.iter()) {
+                            *p1 = blend(*p0, *p1);
+                        }
+                    });
+            }
+            #[cfg(not(feature = "parallel"))]
+            for (p1, p0) in below.iter_mut().zip(top.pixels.iter()) {
+                *p1 = blend(*p0, *p1);
             }
-            self.bitmaps.pop_front();
         }
     }
 
-    fn clip(&mut self) {
-        if self.bitmaps.len() >= 2 {
-            for y in 0..600 {
-                for x in 0..600 {
-                    let ix = (y * 600 + x) as usize;
-                    let pixel0 = self.bitmaps[0].pixels[ix];
-                    let a0 = pixel0.alpha as usize;
-                    let pixel1 = self.bitmaps[1].pixels[ix];
-                    let r1 = pixel1.color.r as usize;
-                    let g1 = pixel1.color.g as usize;
-                    let b1 = pixel1.color.b as usize;
-                    let a1 = pixel1.alpha as usize;
-                    let pixel = Pixel::new(
-                        (r1 * a0 / 255) as Component,
-                        (g1 * a0 / 255) as Component,
-                        (b1 * a0 / 255) as Component,
-                        (a1 * a0 / 255) as Transparency,
-                    );
-                    self.bitmaps[1].pixels[ix] = pixel;
-                }
-            }
-            self.bitmaps.pop_front();
+    /// The channels of `p` as one flat array, alpha last.
+    fn channels(p: Pixel) -> [u32; 4] {
+        [
+            p.color.r as u32,
+            p.color.g as u32,
+            p.color.b as u32,
+            p.alpha as u32,
+        ]
+    }
+
+    fn from_channels(c: [u32; 4]) -> Pixel {
+        Pixel::new(
+            c[0] as Component,
+            c[1] as Component,
+            c[2] as Component,
+            c[3] as Transparency,
+        )
+    }
+
+    fn compose_pixel(p0: Pixel, p1: Pixel) -> Pixel {
+        let (c0, c1) = (Fuun::channels(p0), Fuun::channels(p1));
+        let a = 255 - c0[3];
+        let mut c = [0; 4];
+        for i in 0..4 {
+            c[i] = c0[i] + c1[i] * a / 255;
         }
+        Fuun::from_channels(c)
     }
 
-    pub fn step(&mut self, steps: usize) -> (Bitmap, bool) {
+    fn clip_pixel(p0: Pixel, p1: Pixel) -> Pixel {
+        let c1 = Fuun::channels(p1);
+        let a = p0.alpha as u32;
+        let mut c = [0; 4];
+        for i in 0..4 {
+            c[i] = c1[i] * a / 255;
+        }
+        Fuun::from_channels(c)
+    }
+
+    fn compose(&mut self) {
+        self.blend(Fuun::compose_pixel);
+    }
+
+    fn clip(&mut self) {
+        self.blend(Fuun::clip_pixel);
+    }
+
+    /// Runs up to `steps` more commands, see `current_bitmap` for the result.
+    pub fn step(&mut self, steps: usize) -> Progress {
         let start = self.step;
         let end = std::cmp::min(self.step + steps, self.rna.len());
         for s in start..end {
-            let code = &self.rna[s];
-            match code.as_str() {
-                "PIPIIIC" => self.add_color(Color::Rgb(BLACK)),
-                "PIPIIIP" => self.add_color(Color::Rgb(RED)),
-                "PIPIICC" => self.add_color(Color::Rgb(GREEN)),
-                "PIPIICF" => self.add_color(Color::Rgb(YELLOW)),
-                "PIPIICP" => self.add_color(Color::Rgb(BLUE)),
-                "PIPIIFC" => self.add_color(Color::Rgb(MAGENTA)),
-                "PIPIIFF" => self.add_color(Color::Rgb(CYAN)),
-                "PIPIIPC" => self.add_color(Color::Rgb(WHITE)),
-                "PIPIIPF" => self.add_color(Color::Transparency(TRANSPARENT)),
-                "PIPIIPP" => self.add_color(Color::Transparency(OPAQUE)),
-                "PIIPICP" => {
+            match self.rna[s] {
+                RnaCommand::AddBlack => self.add_color(Color::Rgb(BLACK)),
+                RnaCommand::AddRed => self.add_color(Color::Rgb(RED)),
+                RnaCommand::AddGreen => self.add_color(Color::Rgb(GREEN)),
+                RnaCommand::AddYellow => self.add_color(Color::Rgb(YELLOW)),
+                RnaCommand::AddBlue => self.add_color(Color::Rgb(BLUE)),
+                RnaCommand::AddMagenta => self.add_color(Color::Rgb(MAGENTA)),
+                RnaCommand::AddCyan => self.add_color(Color::Rgb(CYAN)),
+                RnaCommand::AddWhite => self.add_color(Color::Rgb(WHITE)),
+                RnaCommand::AddTransparent => self.add_color(Color::Transparency(TRANSPARENT)),
+                RnaCommand::AddOpaque => self.add_color(Color::Transparency(OPAQUE)),
+                RnaCommand::ClearBucket => {
                     self.current = None;
-                    self.bucket.clear();
+                    self.bucket = Bucket::default();
                 }
-                "PIIIIIP" => self.position = Fuun::move_dir(self.position, self.dir),
-                "PCCCCCP" => self.dir = Fuun::turn_ccw(self.dir),
-                "PFFFFFP" => self.dir = Fuun::turn_cw(self.dir),
-                "PCCIFFP" => self.mark = self.position,
-                "PFFICCP" => self.line(self.position, self.mark),
-                "PIIPIIP" => self.try_fill(),
-                "PCCPFFP" => self.add_bitmap(),
-                "PFFPCCP" => self.compose(),
-                "PFFICCF" => self.clip(),
-                _ => {}
+                RnaCommand::Move => self.position = self.move_dir(self.position, self.dir),
+                RnaCommand::TurnCounterClockwise => self.dir = Fuun::turn_ccw(self.dir),
+                RnaCommand::TurnClockwise => self.dir = Fuun::turn_cw(self.dir),
+                RnaCommand::Mark => self.mark = self.position,
+                RnaCommand::Line => self.line(self.position, self.mark),
+                RnaCommand::TryFill => self.try_fill(),
+                RnaCommand::AddBitmap => self.add_bitmap(),
+                RnaCommand::Compose => self.compose(),
+                RnaCommand::Clip => self.clip(),
+                RnaCommand::Unknown(_) => {}
             }
         }
         self.step = end;
         log::info!("step: {} / {}", self.step, self.rna.len());
-        (self.bitmaps[0].clone(), self.step == self.rna.len())
+        Progress {
+            steps: self.step,
+            total: self.rna.len(),
+        }
     }
 
-    pub fn build(&mut self) -> Bitmap {
-        let (bmp, _done) = self.step(self.rna.len() - self.step);
-        bmp
+    /// Runs all the remaining commands.
+    pub fn build(&mut self) -> &Bitmap {
+        self.step(self.rna.len() - self.step);
+        self.current_bitmap()
     }
 
-    pub fn is_draw_command(rna: &str) -> bool {
-        match rna {
-            "PFFICCP" => true,
-            "PIIPIIP" => true,
-            "PFFPCCP" => true,
-            "PFFICCF" => true,
-            _ => false,
-        }
+    /// The bitmap being drawn on.
+    pub fn current_bitmap(&self) -> &Bitmap {
+        &self.bitmaps[0]
+    }
+
+    /// All the bitmaps, the one being drawn on first.
+    pub fn bitmap_stack(&self) -> &VecDeque<Bitmap> {
+        &self.bitmaps
+    }
+
+    pub fn is_draw_command(rna: RnaCommand) -> bool {
+        rna.is_draw()
     }
 }
 
@@ -418,22 +612,22 @@ mod tests {
         let c = Color::Rgb(CYAN);
         let t = Color::Transparency(TRANSPARENT);
         let o = Color::Transparency(OPAQUE);
-        let mut fuun_1 = Fuun::new("");
+        let mut fuun_1 = Fuun::new(Canvas::default());
         fuun_1.add_color(t);
         fuun_1.add_color(o);
         fuun_1.add_color(o);
         assert_eq!(fuun_1.current_pixel(), Pixel::new(0, 0, 0, 170));
-        let mut fuun_2 = Fuun::new("");
+        let mut fuun_2 = Fuun::new(Canvas::default());
         fuun_2.add_color(b);
         fuun_2.add_color(y);
         fuun_2.add_color(c);
         assert_eq!(fuun_2.current_pixel(), Pixel::new(85, 170, 85, 255));
-        let mut fuun_3 = Fuun::new("");
+        let mut fuun_3 = Fuun::new(Canvas::default());
         fuun_3.add_color(y);
         fuun_3.add_color(t);
         fuun_3.add_color(o);
         assert_eq!(fuun_3.current_pixel(), Pixel::new(127, 127, 0, 127));
-        let mut fuun_4 = Fuun::new("");
+        let mut fuun_4 = Fuun::new(Canvas::default());
         for _ in 0..18 {
             fuun_4.add_color(b);
         }
@@ -452,4 +646,254 @@ mod tests {
         fuun_4.add_color(t);
         assert_eq!(fuun_4.current_pixel(), Pixel::new(143, 25, 125, 191));
     }
+
+    #[test]
+    fn test_step() {
+        let mut fuun = Fuun::new(Canvas::default());
+        let load = fuun
+            .add_rna_str("PCCIFFPPIIIIIPPIIIIIPPIIIIIPPFFICCPICFPICF")
+            .unwrap();
+        assert_eq!(load.unknown, 1);
+        assert_eq!(fuun.remaining_steps(), 6);
+        assert!(Fuun::is_draw_command(fuun.rna[4]));
+        assert_eq!(fuun.step(4), Progress { steps: 4, total: 6 });
+        assert!(!fuun.step(0).is_done());
+        assert!(fuun.step(6).is_done());
+        let bmp = fuun.current_bitmap();
+        let black = Pixel::new(0, 0, 0, OPAQUE);
+        assert_eq!(
+            &bmp.pixels[..5],
+            &[black, black, black, black, Pixel::new(0, 0, 0, 0)]
+        );
+        assert_eq!(bmp.pixels[600], Pixel::new(0, 0, 0, 0));
+    }
+
+    #[test]
+    fn test_load() {
+        let mut fuun = Fuun::new(Canvas::default());
+        assert_eq!(
+            fuun.add_rna_str("PIPIIIC\nICFPICF\r\n"),
+            Ok(RnaLoad {
+                commands: 2,
+                unknown: 1,
+                whitespace: 3
+            })
+        );
+        assert_eq!(
+            fuun.add_rna_str("PIPIIICPIPI\n"),
+            Err(RnaLoadError::PartialCommand { offset: 7, len: 4 })
+        );
+        assert_eq!(
+            fuun.add_rna_bytes(b"PIPIIIC\xc3\xa9"),
+            Err(RnaLoadError::InvalidByte {
+                offset: 7,
+                found: 0xc3
+            })
+        );
+        assert_eq!(fuun.remaining_steps(), 2);
+    }
+
+    /// A xorshift generator, so the random tests are the same every run.
+    fn rng(mut x: u64) -> impl FnMut() -> u64 {
+        move || {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        }
+    }
+
+    /// The fill from before the scanline one, a pixel at a time.
+    fn naive_fill(pixels: &mut [Pixel], pos: Pos, initial: Pixel, new: Pixel) {
+        let mut todo = vec![pos];
+        while let Some(p) = todo.pop() {
+            let ix = (p.y * 600 + p.x) as usize;
+            if pixels[ix] == initial {
+                pixels[ix] = new;
+                if p.x > 0 {
+                    todo.push(Pos { x: p.x - 1, y: p.y });
+                }
+                if p.x < 599 {
+                    todo.push(Pos { x: p.x + 1, y: p.y });
+                }
+                if p.y > 0 {
+                    todo.push(Pos { x: p.x, y: p.y - 1 });
+                }
+                if p.y < 599 {
+                    todo.push(Pos { x: p.x, y: p.y + 1 });
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_fill() {
+        let mut random = rng(0x2545_f491_4f6c_dd1d);
+        let white = Pixel::new(255, 255, 255, 255);
+        for density in &[2, 3, 5] {
+            let mut fuun = Fuun::new(Canvas::default());
+            for p in fuun.bitmaps[0].pixels.iter_mut() {
+                if random().is_multiple_of(*density) {
+                    *p = white;
+                }
+            }
+            fuun.add_color(Color::Rgb(RED));
+            for _ in 0..20 {
+                let pos = Pos {
+                    x: (random() % 600) as Coord,
+                    y: (random() % 600) as Coord,
+                };
+                let initial = fuun.get_pixel(pos);
+                if initial == fuun.current_pixel() {
+                    continue;
+                }
+                let mut expected = fuun.bitmaps[0].pixels.clone();
+                naive_fill(&mut expected, pos, initial, fuun.current_pixel());
+                let changed = expected
+                    .iter()
+                    .zip(fuun.bitmaps[0].pixels.iter())
+                    .filter(|(a, b)| a != b)
+                    .count();
+                assert_eq!(fuun.fill(pos, initial), changed);
+                assert!(fuun.bitmaps[0].pixels == expected);
+            }
+        }
+        let mut fuun = Fuun::new(Canvas::default());
+        fuun.add_rna_str("PIPIIIPPIIPIIP").unwrap();
+        fuun.build();
+        assert_eq!(fuun.filled_pixels(), 360000);
+    }
+
+    /// The compose and clip from before `blend`.
+    fn naive_compose(fuun: &mut Fuun) {
+        if fuun.bitmaps.len() >= 2 {
+            for y in 0..600 {
+                for x in 0..600 {
+                    let ix = (y * 600 + x) as usize;
+                    let pixel0 = fuun.bitmaps[0].pixels[ix];
+                    let r0 = pixel0.color.r as usize;
+                    let g0 = pixel0.color.g as usize;
+                    let b0 = pixel0.color.b as usize;
+                    let a0 = pixel0.alpha as usize;
+                    let pixel1 = fuun.bitmaps[1].pixels[ix];
+                    let r1 = pixel1.color.r as usize;
+                    let g1 = pixel1.color.g as usize;
+                    let b1 = pixel1.color.b as usize;
+                    let a1 = pixel1.alpha as usize;
+                    let pixel = Pixel::new(
+                        (r0 + r1 * (255 - a0) / 255) as Component,
+                        (g0 + g1 * (255 - a0) / 255) as Component,
+                        (b0 + b1 * (255 - a0) / 255) as Component,
+                        (a0 + a1 * (255 - a0) / 255) as Transparency,
+                    );
+                    fuun.bitmaps[1].pixels[ix] = pixel;
+                }
+            }
+            fuun.bitmaps.pop_front();
+        }
+    }
+
+    fn naive_clip(fuun: &mut Fuun) {
+        if fuun.bitmaps.len() >= 2 {
+            for y in 0..600 {
+                for x in 0..600 {
+                    let ix = (y * 600 + x) as usize;
+                    let pixel0 = fuun.bitmaps[0].pixels[ix];
+                    let a0 = pixel0.alpha as usize;
+                    let pixel1 = fuun.bitmaps[1].pixels[ix];
+                    let r1 = pixel1.color.r as usize;
+                    let g1 = pixel1.color.g as usize;
+                    let b1 = pixel1.color.b as usize;
+                    let a1 = pixel1.alpha as usize;
+                    let pixel = Pixel::new(
+                        (r1 * a0 / 255) as Component,
+                        (g1 * a0 / 255) as Component,
+                        (b1 * a0 / 255) as Component,
+                        (a1 * a0 / 255) as Transparency,
+                    );
+                    fuun.bitmaps[1].pixels[ix] = pixel;
+                }
+            }
+            fuun.bitmaps.pop_front();
+        }
+    }
+
+    #[test]
+    fn test_blend() {
+        let mut random = rng(0x9e37_79b9_7f4a_7c15);
+        let mut random_bitmap = || {
+            let mut bmp = Bitmap::new(Canvas::default());
+            for p in bmp.pixels.iter_mut() {
+                let bits = random();
+                // mostly premultiplied pixels as drawn, but some that aren't
+                let a = (bits >> 24) as u8;
+                let limit = if bits & 0x7 == 0 { 255 } else { a as u64 };
+                let c = |shift: u64| ((bits >> shift & 0xff) * limit / 255) as u8;
+                *p = Pixel::new(c(0), c(8), c(16), a);
+            }
+            bmp
+        };
+        for _ in 0..3 {
+            let mut fuun = Fuun::new(Canvas::default());
+            fuun.bitmaps = vec![random_bitmap(), random_bitmap(), random_bitmap()]
+                .into_iter()
+                .collect();
+            let mut expected = Fuun::new(Canvas::default());
+            expected.bitmaps = fuun.bitmaps.clone();
+            fuun.compose();
+            naive_compose(&mut expected);
+            assert!(fuun.bitmaps[0].pixels == expected.bitmaps[0].pixels);
+            fuun.clip();
+            naive_clip(&mut expected);
+            assert_eq!(fuun.bitmaps.len(), 1);
+            assert!(fuun.bitmaps[0].pixels == expected.bitmaps[0].pixels);
+            // with a single bitmap there's nothing to do
+            fuun.compose();
+            assert!(fuun.bitmaps[0].pixels == expected.bitmaps[0].pixels);
+        }
+    }
+
+    #[test]
+    fn test_canvas() {
+        let canvas = Canvas {
+            width: 4,
+            height: 3,
+        };
+        let mut fuun = Fuun::new(canvas);
+        // west off the left edge to (3, 0), south to (3, 2), mark, south off
+        // the bottom edge back to (3, 0) and draw a line to the mark
+        let rna = [
+            "PCCCCCP", "PCCCCCP", "PIIIIIP", "PCCCCCP", "PIIIIIP", "PIIIIIP", "PCCIFFP", "PIIIIIP",
+            "PFFICCP",
+        ];
+        fuun.add_rna_str(&rna.concat()).unwrap();
+        // then west to (2, 0) and fill what's left in red
+        fuun.add_rna_str("PIPIIIPPFFFFFPPIIIIIPPIIPIIP").unwrap();
+        fuun.add_rna_str("PCCPFFP").unwrap();
+        assert_eq!(fuun.build().pixels, vec![Pixel::new(0, 0, 0, 0); 12]);
+        assert_eq!(fuun.bitmap_stack().len(), 2);
+        let bmp = &fuun.bitmap_stack()[1];
+        assert_eq!(bmp.canvas, canvas);
+        let black = Pixel::new(0, 0, 0, OPAQUE);
+        let red = Pixel::new(255, 0, 0, OPAQUE);
+        let row = [red, red, red, black];
+        assert_eq!(bmp.pixels, [&row[..], &row[..], &row[..]].concat());
+        assert_eq!(fuun.filled_pixels(), 9);
+        // an empty canvas still has the one pixel to draw on
+        let mut fuun = Fuun::new(Canvas {
+            width: 0,
+            height: 0,
+        });
+        fuun.add_rna_str("PIPIIIPPIIIIIPPCCIFFPPFFICCPPIIPIIP")
+            .unwrap();
+        let bmp = fuun.build();
+        assert_eq!(
+            bmp.canvas,
+            Canvas {
+                width: 1,
+                height: 1
+            }
+        );
+        assert_eq!(bmp.pixels, vec![red]);
+    }
 }
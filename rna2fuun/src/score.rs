@@ -0,0 +1,76 @@
+//! Comparing a drawn bitmap against the target picture.
+//!
+//! The contest's risk is the number of pixels whose color differs from the
+//! target. Alpha is ignored, as it is in the final picture.
+
+use crate::{Bitmap, Pixel};
+
+fn channel_diff(a: &Pixel, b: &Pixel) -> usize {
+    let d = |x: u8, y: u8| x.abs_diff(y) as usize;
+    d(a.color.r, b.color.r) + d(a.color.g, b.color.g) + d(a.color.b, b.color.b)
+}
+
+/// Number of pixels of `bitmap` whose color isn't the one in `target`.
+///
+/// Panics if the two aren't the same size.
+pub fn risk(bitmap: &Bitmap, target: &Bitmap) -> usize {
+    assert_eq!(bitmap.canvas, target.canvas);
+    bitmap
+        .pixels
+        .iter()
+        .zip(target.pixels.iter())
+        .filter(|(a, b)| a.color != b.color)
+        .count()
+}
+
+/// A picture of where `bitmap` and `target` differ. Pixels that match are
+/// the target in dark gray, the others red, brighter the bigger the
+/// difference.
+///
+/// Panics if the two aren't the same size.
+pub fn heatmap(bitmap: &Bitmap, target: &Bitmap) -> Bitmap {
+    assert_eq!(bitmap.canvas, target.canvas);
+    let pixels = bitmap
+        .pixels
+        .iter()
+        .zip(target.pixels.iter())
+        .map(|(a, b)| {
+            if a.color == b.color {
+                let gray = (b.color.r as usize + b.color.g as usize + b.color.b as usize) / 12;
+                Pixel::new(gray as u8, gray as u8, gray as u8, 255)
+            } else {
+                let red = 64 + channel_diff(a, b) * 191 / (3 * 255);
+                Pixel::new(red as u8, 0, 0, 255)
+            }
+        })
+        .collect();
+    Bitmap {
+        canvas: bitmap.canvas,
+        pixels,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Canvas;
+
+    #[test]
+    fn risk_and_heatmap() {
+        let mut bitmap = Bitmap::new(Canvas::default());
+        let mut target = Bitmap::new(Canvas::default());
+        assert_eq!(risk(&bitmap, &target), 0);
+        target.pixels[0] = Pixel::new(255, 255, 255, 255);
+        target.pixels[1] = Pixel::new(0, 0, 3, 255);
+        // only the alpha differs
+        bitmap.pixels[2] = Pixel::new(0, 0, 0, 255);
+        target.pixels[3] = Pixel::new(120, 120, 120, 255);
+        bitmap.pixels[3] = Pixel::new(120, 120, 120, 0);
+        assert_eq!(risk(&bitmap, &target), 2);
+        let map = heatmap(&bitmap, &target);
+        assert_eq!(map.pixels[0], Pixel::new(255, 0, 0, 255));
+        assert_eq!(map.pixels[1], Pixel::new(64, 0, 0, 255));
+        assert_eq!(map.pixels[2], Pixel::new(0, 0, 0, 255));
+        assert_eq!(map.pixels[3], Pixel::new(30, 30, 30, 255));
+    }
+}